@@ -0,0 +1,292 @@
+//! Procedural macros for [`axum-sqlx-tx`](https://docs.rs/axum-sqlx-tx).
+//!
+//! These are re-exported by `axum-sqlx-tx` with the `macros` feature, and should be used from
+//! there.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, spanned::Spanned, FnArg, Ident, ItemFn, LitInt,
+    LitStr, PatType, ReturnType, Type,
+};
+
+/// Run a handler as a transaction, with options declared next to it.
+///
+/// The handler must take a `Tx` parameter (i.e. a type named `Tx`, such as an alias of
+/// `axum_sqlx_tx::Tx<DB>`, or the type given with the `tx` option). The attribute turns it into a handler that extracts
+/// `axum_sqlx_tx::Transactional<Tx>` instead, and runs the original handler with
+/// `Transactional::run`. The transaction is resolved according to the handler's response before
+/// it's returned.
+///
+/// ```ignore
+/// use axum_sqlx_tx::transactional;
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// #[transactional(isolation = "serializable", retry = 3, timeout = "5s")]
+/// async fn transfer(mut tx: Tx) -> Result<(), axum_sqlx_tx::Error> {
+///     sqlx::query("...").execute(&mut tx).await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The supported options are:
+///
+/// - `isolation = "..."`: the isolation level, one of `"read uncommitted"`, `"read committed"`,
+///   `"repeatable read"` or `"serializable"`.
+/// - `read_only`: begin a read-only transaction.
+/// - `retry = n`: retry the handler up to `n` times after a serialization failure or deadlock.
+///   The handler's other arguments must implement `Clone` to be passed to each attempt.
+/// - `timeout = "..."`: roll back and respond with `Error::TimedOut` if an attempt takes longer
+///   than the given duration, e.g. `"500ms"`, `"5s"` or `"1m"`.
+/// - `state = AppState`: check that `Tx` can be extracted with the router state `AppState`, to get
+///   a clear error if the database marker types don't match.
+/// - `tx = MyTx`: the type of the transaction parameter, for aliases that aren't named `Tx`, e.g.
+///   when using several databases:
+///
+/// ```ignore
+/// type AccountsTx = axum_sqlx_tx::Tx<Accounts>;
+///
+/// #[transactional(tx = AccountsTx, retry = 3)]
+/// async fn transfer(mut tx: AccountsTx) -> Result<(), axum_sqlx_tx::Error> {
+///     /* ... */
+///     Ok(())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn transactional(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut options = Options::default();
+    let parser = syn::meta::parser(|meta| options.parse(meta));
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(item as ItemFn);
+
+    expand(options, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct Options {
+    isolation: Option<Ident>,
+    read_only: bool,
+    retry: u32,
+    timeout_ms: Option<u64>,
+    state: Option<Type>,
+    tx: Option<Type>,
+}
+
+impl Options {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("isolation") {
+            let value: LitStr = meta.value()?.parse()?;
+            let level = value.value().to_lowercase().replace(['_', '-'], " ");
+            let variant = match level.as_str() {
+                "read uncommitted" => "ReadUncommitted",
+                "read committed" => "ReadCommitted",
+                "repeatable read" => "RepeatableRead",
+                "serializable" => "Serializable",
+                _ => {
+                    return Err(syn::Error::new(
+                        value.span(),
+                        "unknown isolation level; expected \"read uncommitted\", \
+                         \"read committed\", \"repeatable read\" or \"serializable\"",
+                    ))
+                }
+            };
+            self.isolation = Some(Ident::new(variant, value.span()));
+        } else if meta.path.is_ident("read_only") {
+            self.read_only = true;
+        } else if meta.path.is_ident("retry") {
+            let value: LitInt = meta.value()?.parse()?;
+            self.retry = value.base10_parse()?;
+        } else if meta.path.is_ident("timeout") {
+            let value: LitStr = meta.value()?.parse()?;
+            self.timeout_ms = Some(parse_duration(&value)?);
+        } else if meta.path.is_ident("state") {
+            self.state = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("tx") {
+            self.tx = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error(
+                "unknown option; expected `isolation`, `read_only`, `retry`, `timeout`, `state` or \
+                 `tx`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Parse a duration like `"500ms"`, `"5s"`, `"1m"` or `"1h"` into milliseconds.
+fn parse_duration(value: &LitStr) -> syn::Result<u64> {
+    let error = || {
+        syn::Error::new(
+            value.span(),
+            "invalid duration; expected a whole number with a unit, e.g. \"500ms\", \"5s\" or \"1m\"",
+        )
+    };
+    let value = value.value();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(error)?;
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| error())?;
+    let scale = match unit.trim() {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => return Err(error()),
+    };
+    number.checked_mul(scale).ok_or_else(error)
+}
+
+fn expand(options: Options, item: ItemFn) -> syn::Result<TokenStream2> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new(
+            sig.fn_token.span(),
+            "`#[transactional]` handlers must be `async`",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "`#[transactional]` handlers can't be generic",
+        ));
+    }
+
+    // Find the `Tx` parameter, and name the others so that they can be passed through
+    let tx_name = match &options.tx {
+        Some(tx) => quote!(#tx).to_string(),
+        None => "Tx".to_string(),
+    };
+    let mut tx = None;
+    let mut params = Vec::new();
+    let mut call_args = Vec::new();
+    for (index, input) in sig.inputs.iter().enumerate() {
+        let FnArg::Typed(PatType { ty, .. }) = input else {
+            return Err(syn::Error::new(
+                input.span(),
+                "`#[transactional]` handlers can't take `self`",
+            ));
+        };
+        if is_tx(ty, options.tx.as_ref()) {
+            if tx.is_some() {
+                return Err(syn::Error::new(
+                    ty.span(),
+                    format!("`#[transactional]` handlers must take a single `{tx_name}` parameter"),
+                ));
+            }
+            tx = Some(ty.clone());
+            call_args.push(quote!(__tx));
+        } else {
+            let ident = format_ident!("__arg{}", index);
+            call_args.push(if options.retry > 0 {
+                quote!(::core::clone::Clone::clone(&#ident))
+            } else {
+                quote!(#ident)
+            });
+            params.push((ident, ty.clone()));
+        }
+    }
+    let Some(tx) = tx else {
+        return Err(syn::Error::new(
+            sig.ident.span(),
+            format!("`#[transactional]` handlers must take a `{tx_name}` parameter"),
+        ));
+    };
+
+    let name = &sig.ident;
+    let inputs = &sig.inputs;
+    let output = match &sig.output {
+        ReturnType::Default => quote!(),
+        output @ ReturnType::Type(..) => quote!(#output),
+    };
+    let handler = Ident::new("__handler", Span::mixed_site());
+    let param_idents: Vec<_> = params.iter().map(|(ident, _)| ident).collect();
+    let param_decls = params.iter().map(|(ident, ty)| quote!(#ident: #ty));
+
+    let mut builder = quote!();
+    if options.isolation.is_some() || options.read_only {
+        let mut tx_options = quote!(::axum_sqlx_tx::TxOptions::new());
+        if let Some(isolation) = &options.isolation {
+            tx_options = quote!(#tx_options.isolation(::axum_sqlx_tx::IsolationLevel::#isolation));
+        }
+        if options.read_only {
+            tx_options = quote!(#tx_options.read_only(true));
+        }
+        builder = quote!(#builder.options(#tx_options));
+    }
+    if options.retry > 0 {
+        let retry = options.retry;
+        builder = quote!(#builder.retry(#retry));
+    }
+    if let Some(timeout_ms) = options.timeout_ms {
+        builder = quote!(#builder.timeout(::core::time::Duration::from_millis(#timeout_ms)));
+    }
+
+    let assert_state = options.state.map(|state| {
+        quote! {
+            ::axum_sqlx_tx::__private::assert_tx_state::<#tx, #state>();
+        }
+    });
+
+    // Without retries, the arguments are moved into the only attempt
+    let run = if options.retry > 0 {
+        quote! {
+            .run(move |__tx| #handler(#(#call_args),*))
+        }
+    } else {
+        quote! {
+            .run({
+                let mut __args = ::core::option::Option::Some((#(#param_idents,)*));
+                move |__tx| {
+                    let (#(#param_idents,)*) = __args
+                        .take()
+                        .expect("`#[transactional]` handler called more than once without `retry`");
+                    #handler(#(#call_args),*)
+                }
+            })
+        }
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis async fn #name(
+            __transactional: ::axum_sqlx_tx::Transactional<#tx>,
+            #(#param_decls,)*
+        ) -> ::axum_sqlx_tx::__private::Response {
+            async fn #handler(#inputs) #output #block
+
+            #assert_state
+
+            __transactional
+                #builder
+                #run
+                .await
+        }
+    })
+}
+
+/// Whether `ty` is the transaction type given with the `tx` option, or otherwise looks like a `Tx`
+/// type, i.e. its last path segment is `Tx`.
+fn is_tx(ty: &Type, tx: Option<&Type>) -> bool {
+    if let Some(tx) = tx {
+        return quote!(#ty).to_string() == quote!(#tx).to_string();
+    }
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Tx"),
+        _ => false,
+    }
+}
@@ -0,0 +1,199 @@
+use std::{cmp::Reverse, collections::BTreeMap, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+/// What to do when a transaction is requested while the
+/// [transaction limit](crate::Config::max_transactions) is reached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Saturation {
+    /// Wait for another transaction to resolve, for up to the pool's acquire timeout.
+    ///
+    /// The time spent waiting counts towards the acquire timeout, so once admitted, a connection is
+    /// only waited for until the acquire timeout has elapsed in total.
+    ///
+    /// If the timeout elapses, the extractor fails with
+    /// [`Error::Saturated`](crate::Error::Saturated).
+    #[default]
+    Wait,
+
+    /// Fail immediately with [`Error::Saturated`](crate::Error::Saturated).
+    Reject,
+}
+
+/// The priority of a request's transaction.
+///
+/// When the number of transactions is limited (see
+/// [`Config::max_transactions`](crate::Config::max_transactions) and
+/// [`Config::reserve`](crate::Config::reserve)), waiting requests with a higher priority are
+/// admitted first. Requests have [`Priority::Normal`] unless a `Priority` is present in the
+/// [request extensions], which can be done per-route with `axum::Extension`:
+///
+/// ```
+/// use axum::{routing::get, Extension};
+/// use axum_sqlx_tx::Priority;
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// let (state, layer) = Tx::config(pool)
+///     // Keep one connection free for high priority requests
+///     .reserve(Priority::High, 1)
+///     .setup();
+///
+/// let app = axum::Router::new()
+///     .route("/checkout", get(checkout).route_layer(Extension(Priority::High)))
+///     .route("/reports", get(report).route_layer(Extension(Priority::Low)))
+///     .layer(layer)
+///     .with_state(state);
+/// # let listener: tokio::net::TcpListener = todo!();
+/// # axum::serve(listener, app);
+/// # }
+/// # async fn checkout(_: Tx) {}
+/// # async fn report(_: Tx) {}
+/// ```
+///
+/// [request extensions]: https://docs.rs/http/latest/http/struct.Extensions.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Served after all other requests.
+    Low,
+    /// The default priority.
+    #[default]
+    Normal,
+    /// Served before all other requests.
+    High,
+}
+
+impl Priority {
+    const ALL: [Self; 3] = [Self::Low, Self::Normal, Self::High];
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Limits the number of concurrent transactions, admitting waiters in priority order.
+#[derive(Debug)]
+pub(crate) struct Admission {
+    gate: Arc<Gate>,
+    saturation: Saturation,
+}
+
+#[derive(Debug)]
+struct Gate {
+    reserved: [usize; 3],
+    queue: Mutex<Queue>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    available: usize,
+    next_ticket: u64,
+    waiters: BTreeMap<(Reverse<Priority>, u64), oneshot::Sender<Permit>>,
+}
+
+/// Permission to hold a transaction, released on drop.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    gate: Option<Arc<Gate>>,
+}
+
+impl Admission {
+    pub(crate) fn new(limit: usize, reserved: [usize; 3], saturation: Saturation) -> Self {
+        let gate = Gate {
+            reserved,
+            queue: Mutex::new(Queue {
+                available: limit,
+                next_ticket: 0,
+                waiters: BTreeMap::new(),
+            }),
+        };
+        Self {
+            gate: Arc::new(gate),
+            saturation,
+        }
+    }
+
+    /// Wait for a permit, or return `None` if one couldn't be obtained.
+    pub(crate) async fn admit(&self, priority: Priority, timeout: Duration) -> Option<Permit> {
+        let rx = {
+            let mut queue = self.gate.queue.lock();
+            queue.waiters.retain(|_, tx| !tx.is_closed());
+
+            // Only jump the queue if nobody of the same or higher priority is waiting
+            let queued = queue
+                .waiters
+                .keys()
+                .next()
+                .is_some_and(|(Reverse(waiting), _)| *waiting >= priority);
+            if !queued && self.gate.admits(&queue, priority) {
+                queue.available -= 1;
+                return Some(Permit {
+                    gate: Some(self.gate.clone()),
+                });
+            }
+
+            if self.saturation == Saturation::Reject {
+                return None;
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let ticket = queue.next_ticket;
+            queue.next_ticket += 1;
+            queue.waiters.insert((Reverse(priority), ticket), tx);
+            rx
+        };
+
+        // If we time out, the waiter is skipped when it's next considered. A permit that was sent
+        // just as we timed out is dropped along with the receiver, which returns it to the gate.
+        tokio::time::timeout(timeout, rx).await.ok()?.ok()
+    }
+}
+
+impl Gate {
+    /// The number of permits that are reserved for priorities above `priority`.
+    fn reserved_above(&self, priority: Priority) -> usize {
+        Priority::ALL
+            .iter()
+            .filter(|other| **other > priority)
+            .map(|other| self.reserved[other.index()])
+            .sum()
+    }
+
+    fn admits(&self, queue: &Queue, priority: Priority) -> bool {
+        queue.available > self.reserved_above(priority)
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut queue = self.queue.lock();
+        queue.available += 1;
+
+        while let Some((&key, _)) = queue
+            .waiters
+            .iter()
+            .find(|((Reverse(priority), _), tx)| !tx.is_closed() && self.admits(&queue, *priority))
+        {
+            let tx = queue.waiters.remove(&key).unwrap();
+            queue.available -= 1;
+
+            let permit = Permit {
+                gate: Some(self.clone()),
+            };
+            if let Err(mut permit) = tx.send(permit) {
+                // The waiter gave up – put the permit back without re-entering `release`
+                permit.gate = None;
+                queue.available += 1;
+            }
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(gate) = self.gate.take() {
+            gate.release();
+        }
+    }
+}
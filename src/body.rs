@@ -0,0 +1,343 @@
+//! Response bodies that resolve the transaction when they end, or buffer it to be classified.
+
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures_core::future::BoxFuture;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, Frame, SizeHint};
+
+use crate::{extension::Extension, layer::finish, Event, Marker, Rollback, State};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+
+/// The trailer reporting the outcome of the transaction, see
+/// [`Config::resolve_on_body_end`](crate::Config::resolve_on_body_end).
+pub(crate) const OUTCOME: &str = "x-transaction-outcome";
+
+/// How a [`Resolving`] body decides the outcome of the transaction.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Policy {
+    /// Commit if the trailers contain `grpc-status: 0`.
+    Grpc,
+
+    /// Commit if the body ends without error, and report the outcome in the trailers.
+    BodyEnd,
+}
+
+impl Policy {
+    /// Whether to commit, given the trailers at the end of the body (if any).
+    fn commit(self, trailers: Option<&HeaderMap>) -> bool {
+        match self {
+            Self::Grpc => trailers.and_then(grpc_ok).unwrap_or(false),
+            Self::BodyEnd => true,
+        }
+    }
+
+    /// The trailers to send once the transaction has been resolved.
+    fn trailers(
+        self,
+        trailers: Option<HeaderMap>,
+        commit: bool,
+        result: Result<(), sqlx::Error>,
+    ) -> Option<HeaderMap> {
+        match (self, commit, result) {
+            (Self::Grpc, true, Err(_)) => {
+                let mut trailers = trailers.unwrap_or_default();
+                grpc_internal(&mut trailers);
+                Some(trailers)
+            }
+            (Self::Grpc, _, _) => trailers,
+            (Self::BodyEnd, commit, result) => {
+                let outcome = match (commit, result) {
+                    (true, Ok(())) => "committed",
+                    (true, Err(_)) => "commit-failed",
+                    (false, _) => "rolled-back",
+                };
+                let mut trailers = trailers.unwrap_or_default();
+                trailers.insert(OUTCOME, HeaderValue::from_static(outcome));
+                Some(trailers)
+            }
+        }
+    }
+}
+
+/// Whether the given headers or trailers report a successful gRPC call, if they contain a status.
+pub(crate) fn grpc_ok(headers: &HeaderMap) -> Option<bool> {
+    headers.get(GRPC_STATUS).map(|status| status == "0")
+}
+
+/// Replace the gRPC status in `headers` with `INTERNAL`, after failing to commit.
+pub(crate) fn grpc_internal(headers: &mut HeaderMap) {
+    headers.insert(GRPC_STATUS, HeaderValue::from_static("13"));
+    headers.insert(
+        GRPC_MESSAGE,
+        HeaderValue::from_static("failed to commit transaction"),
+    );
+}
+
+/// A response body that resolves the transaction once the inner body ends.
+///
+/// The end of the body (or its trailers) is held back until the transaction has been resolved, so
+/// that the outcome can be reported in the trailers. A body error always rolls back.
+pub(crate) struct Resolving<DB: Marker, B> {
+    inner: Option<Pin<Box<B>>>,
+    policy: Policy,
+    ext: Option<(Extension<DB>, State<DB>)>,
+    pending: Option<Pending>,
+    done: bool,
+}
+
+struct Pending {
+    resolve: BoxFuture<'static, Result<(), sqlx::Error>>,
+    commit: bool,
+    end: End,
+}
+
+/// How the inner body ended.
+enum End {
+    Trailers(HeaderMap),
+    Eof,
+    Error(BoxError),
+}
+
+impl<DB: Marker, B> Resolving<DB, B> {
+    pub(crate) fn new(inner: B, ext: Extension<DB>, state: State<DB>, policy: Policy) -> Self {
+        Self {
+            inner: Some(Box::pin(inner)),
+            policy,
+            ext: Some((ext, state)),
+            pending: None,
+            done: false,
+        }
+    }
+}
+
+impl<DB: Marker, B> Body for Resolving<DB, B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(pending) = &mut this.pending {
+                let result = ready!(pending.resolve.as_mut().poll(cx));
+                let Pending { commit, end, .. } = this.pending.take().unwrap();
+                this.done = true;
+
+                let trailers = match end {
+                    End::Error(error) => return Poll::Ready(Some(Err(error))),
+                    End::Trailers(trailers) => Some(trailers),
+                    End::Eof => None,
+                };
+                let trailers = this.policy.trailers(trailers, commit, result);
+                return Poll::Ready(trailers.map(|trailers| Ok(Frame::trailers(trailers))));
+            }
+            let Some(inner) = &mut this.inner else {
+                return Poll::Ready(None);
+            };
+
+            let end = match ready!(inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_trailers() {
+                    Ok(trailers) => End::Trailers(trailers),
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(error)) => End::Error(error.into()),
+                None => End::Eof,
+            };
+            let commit = match &end {
+                End::Trailers(trailers) => this.policy.commit(Some(trailers)),
+                End::Eof => this.policy.commit(None),
+                End::Error(_) => false,
+            };
+            // Drop the inner body first, so that any `Tx` it holds is released
+            this.inner = None;
+            let (ext, state) = this.ext.take().expect("BUG: resolved body twice");
+            this.pending = Some(Pending {
+                resolve: Box::pin(async move { finish(ext, &state, commit).await }),
+                commit,
+                end,
+            });
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // The body isn't over until the transaction has been resolved
+        self.done
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        // Never report an exact size: servers stop polling a body once they've written that many
+        // bytes (e.g. for `Content-Length`), so its end would never be seen and the transaction
+        // would be rolled back
+        let mut hint = SizeHint::new();
+        if let Some(inner) = &self.inner {
+            hint.set_lower(inner.size_hint().lower());
+        }
+        hint
+    }
+}
+
+impl<DB: Marker, B> Drop for Resolving<DB, B> {
+    fn drop(&mut self) {
+        // Drop the inner body first, so that any `Tx` it holds is released
+        self.inner = None;
+
+        // If the body is dropped before it ends (e.g. the client disconnected), the transaction is
+        // rolled back when the extension is dropped – unless it should be done explicitly
+        let Some((ext, state)) = self.ext.take() else {
+            return;
+        };
+        if !state.cancellation_safe() {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let rollback = ext.rollback().await;
+                state.observe(Event::Cancelled {
+                    rollback: Rollback::of(&rollback),
+                });
+            });
+        }
+    }
+}
+
+/// A response body that was buffered by the [`Layer`](crate::Layer) to be classified, see
+/// [`Config::body_classifier`](crate::Config::body_classifier).
+///
+/// The buffered data is sent first, followed by the rest of the inner body if buffering stopped
+/// early, or else the inner body's trailers or error.
+pub(crate) struct Buffered<B> {
+    data: Option<Bytes>,
+    rest: Option<Pin<Box<B>>>,
+    trailers: Option<HeaderMap>,
+    error: Option<BoxError>,
+}
+
+impl<B> Buffered<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    /// Buffer up to `limit` bytes of `body`.
+    ///
+    /// Returns the buffered body, and whether `body` was buffered completely (i.e. it ended within
+    /// the limit and without error).
+    pub(crate) async fn collect(body: B, limit: usize) -> (Self, bool) {
+        let mut body = Box::pin(body);
+        let mut data = BytesMut::new();
+        let mut trailers = None;
+        loop {
+            match poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(chunk) => {
+                        data.extend_from_slice(&chunk);
+                        if data.len() > limit {
+                            let buffered = Self {
+                                data: Some(data.freeze()),
+                                rest: Some(body),
+                                trailers: None,
+                                error: None,
+                            };
+                            return (buffered, false);
+                        }
+                    }
+                    Err(frame) => {
+                        if let Ok(frame) = frame.into_trailers() {
+                            trailers = Some(frame);
+                        }
+                    }
+                },
+                Some(Err(error)) => {
+                    let buffered = Self {
+                        data: Some(data.freeze()),
+                        rest: None,
+                        trailers: None,
+                        error: Some(error.into()),
+                    };
+                    return (buffered, false);
+                }
+                None => {
+                    let buffered = Self {
+                        data: Some(data.freeze()),
+                        rest: None,
+                        trailers,
+                        error: None,
+                    };
+                    return (buffered, true);
+                }
+            }
+        }
+    }
+
+    /// The buffered data.
+    pub(crate) fn data(&self) -> &Bytes {
+        static EMPTY: Bytes = Bytes::new();
+        self.data.as_ref().unwrap_or(&EMPTY)
+    }
+}
+
+impl<B> Body for Buffered<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(data) = this.data.take().filter(|data| !data.is_empty()) {
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+        if let Some(rest) = &mut this.rest {
+            return rest.as_mut().poll_frame(cx).map_err(Into::into);
+        }
+        if let Some(error) = this.error.take() {
+            return Poll::Ready(Some(Err(error)));
+        }
+        Poll::Ready(
+            this.trailers
+                .take()
+                .map(|trailers| Ok(Frame::trailers(trailers))),
+        )
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.as_ref().is_none_or(Bytes::is_empty)
+            && self.rest.as_ref().is_none_or(|rest| rest.is_end_stream())
+            && self.error.is_none()
+            && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let buffered = self.data.as_ref().map_or(0, Bytes::len) as u64;
+        let Some(rest) = &self.rest else {
+            return SizeHint::with_exact(buffered);
+        };
+        let rest = rest.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(buffered + rest.lower());
+        if let Some(upper) = rest.upper() {
+            hint.set_upper(buffered + upper);
+        }
+        hint
+    }
+}
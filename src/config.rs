@@ -1,6 +1,9 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, time::Duration};
 
-use crate::{Layer, Marker, State};
+use crate::{
+    admission::Admission, state::Options, BodyClassifier, ConstraintClassifier, Error, Layer,
+    Marker, Observer, OnPanic, Priority, Saturation, State,
+};
 
 /// Configuration for [`Tx`](crate::Tx) extractors.
 ///
@@ -18,17 +21,39 @@ use crate::{Layer, Marker, State};
 /// ```
 pub struct Config<DB: Marker, LayerError> {
     pool: sqlx::Pool<DB::Driver>,
+    retry_after: Option<Duration>,
+    constraints: Option<ConstraintClassifier>,
+    max_transactions: Option<usize>,
+    saturation: Saturation,
+    reserved: [usize; 3],
+    observer: Option<Box<dyn Observer>>,
+    cancellation_safe: bool,
+    on_panic: OnPanic,
+    grpc: bool,
+    resolve_on_body_end: bool,
+    body_classifier: Option<(usize, Box<dyn BodyClassifier>)>,
     _layer_error: PhantomData<LayerError>,
 }
 
 impl<DB: Marker, LayerError> Config<DB, LayerError>
 where
     LayerError: axum_core::response::IntoResponse,
-    sqlx::Error: Into<LayerError>,
+    Error: Into<LayerError>,
 {
     pub(crate) fn new(pool: sqlx::Pool<DB::Driver>) -> Self {
         Self {
             pool,
+            retry_after: None,
+            constraints: None,
+            max_transactions: None,
+            saturation: Saturation::default(),
+            reserved: [0; 3],
+            observer: None,
+            cancellation_safe: false,
+            on_panic: OnPanic::default(),
+            grpc: false,
+            resolve_on_body_end: false,
+            body_classifier: None,
             _layer_error: PhantomData,
         }
     }
@@ -36,17 +61,189 @@ where
     /// Change the layer error type.
     pub fn layer_error<E>(self) -> Config<DB, E>
     where
-        sqlx::Error: Into<E>,
+        Error: Into<E>,
     {
         Config {
             pool: self.pool,
+            retry_after: self.retry_after,
+            constraints: self.constraints,
+            max_transactions: self.max_transactions,
+            saturation: self.saturation,
+            reserved: self.reserved,
+            observer: self.observer,
+            cancellation_safe: self.cancellation_safe,
+            on_panic: self.on_panic,
+            grpc: self.grpc,
+            resolve_on_body_end: self.resolve_on_body_end,
+            body_classifier: self.body_classifier,
             _layer_error: PhantomData,
         }
     }
 
+    /// Set the `Retry-After` duration reported when the pool is exhausted.
+    ///
+    /// When no connection can be acquired before the pool's acquire timeout, the extractor fails
+    /// with [`Error::PoolTimedOut`], which is rendered as HTTP 503. If a duration is set here, the
+    /// response will also include a `Retry-After` header so that clients and load balancers back
+    /// off. By default no `Retry-After` header is sent.
+    pub fn retry_after(mut self, duration: Duration) -> Self {
+        self.retry_after = Some(duration);
+        self
+    }
+
+    /// Map database constraint violations to responses.
+    ///
+    /// See [`ConstraintClassifier`] for details.
+    pub fn constraint_classifier(mut self, classifier: ConstraintClassifier) -> Self {
+        self.constraints = Some(classifier);
+        self
+    }
+
+    /// Limit the number of transactions that can be in flight at once.
+    ///
+    /// This protects the database from load spikes: rather than every request beginning a
+    /// transaction and then timing out waiting for a connection, at most `limit` transactions will
+    /// be open at any time. What happens when the limit is reached is controlled by
+    /// [`Config::on_saturation`].
+    ///
+    /// The limit only applies when a transaction is begun, so requests that never use the
+    /// [`Tx`](crate::Tx) extractor are unaffected.
+    pub fn max_transactions(mut self, limit: usize) -> Self {
+        self.max_transactions = Some(limit);
+        self
+    }
+
+    /// Set what to do when the [transaction limit](Config::max_transactions) is reached.
+    ///
+    /// Defaults to [`Saturation::Wait`].
+    pub fn on_saturation(mut self, saturation: Saturation) -> Self {
+        self.saturation = saturation;
+        self
+    }
+
+    /// Reserve capacity for requests with at least the given [`Priority`].
+    ///
+    /// `count` transactions out of the [limit](Config::max_transactions) will only be given to
+    /// requests with a priority of `priority` or higher. If no limit is set, the pool's maximum
+    /// number of connections is used as the limit.
+    ///
+    /// Regardless of reservations, when transactions are limited waiting requests are admitted in
+    /// priority order. See [`Priority`] for how to set the priority of requests.
+    pub fn reserve(mut self, priority: Priority, count: usize) -> Self {
+        self.reserved[priority.index()] = count;
+        self
+    }
+
+    /// Set an [`Observer`] to be notified of [`Event`](crate::Event)s.
+    pub fn observer(mut self, observer: impl Observer) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Resolve transactions even if the response future is dropped.
+    ///
+    /// If a client disconnects, or a timeout middleware gives up on a request, the response future
+    /// returned by the [`Layer`] middleware is dropped. By default, this simply drops the
+    /// transaction, which queues a `ROLLBACK` to run the next time the connection is used. If the
+    /// future is dropped while committing, the outcome of the commit is unknown.
+    ///
+    /// When enabled, the transaction is committed in a background task once the inner service has
+    /// responded, so the commit runs to completion even if the response future is dropped. If the
+    /// response future is dropped before the inner service responds (or, with a
+    /// [`body_classifier`](Config::body_classifier), while the body is being buffered), the
+    /// transaction is explicitly rolled back in a background task. Both cases are reported to the
+    /// [`observer`](Config::observer).
+    ///
+    /// This requires a Tokio runtime.
+    pub fn cancellation_safe(mut self, enabled: bool) -> Self {
+        self.cancellation_safe = enabled;
+        self
+    }
+
+    /// Set what to do when the inner service panics.
+    ///
+    /// When a handler panics, the [`Layer`] middleware always rolls back the transaction explicitly
+    /// and reports [`Event::Panicked`](crate::Event::Panicked) to the
+    /// [`observer`](Config::observer). By default, the panic is then resumed so that it can be
+    /// handled by outer middleware (e.g. `tower_http::catch_panic`). See [`OnPanic`] for
+    /// alternatives.
+    pub fn on_panic(mut self, on_panic: OnPanic) -> Self {
+        self.on_panic = on_panic;
+        self
+    }
+
+    /// Decide whether to commit based on the gRPC status of responses.
+    ///
+    /// gRPC services (e.g. built with `tonic`) respond with HTTP 200 even when calls fail, and
+    /// report the real status in a `grpc-status` header or trailer. When enabled, the transaction
+    /// is only committed if the `grpc-status` is `0` (`OK`). If the status is in the trailers, the
+    /// transaction is resolved once the response body ends, and a failure to commit is reported by
+    /// replacing the status with `13` (`INTERNAL`). The
+    /// [`constraint_classifier`](Config::constraint_classifier) isn't applied to gRPC responses.
+    ///
+    /// Services can obtain the transaction with
+    /// [`Tx::from_extensions`](crate::Tx::from_extensions), e.g.
+    /// `Tx::from_extensions(request.extensions())` for a `tonic::Request`.
+    pub fn grpc(mut self, enabled: bool) -> Self {
+        self.grpc = enabled;
+        self
+    }
+
+    /// Resolve the transaction once the response body has been sent, rather than before.
+    ///
+    /// By default, the transaction is resolved as soon as the inner service responds, before the
+    /// response body is sent. When enabled, successful responses are sent straight away and the
+    /// transaction is resolved once the body ends. This allows the body to keep using the
+    /// transaction (e.g. to stream query results), and an error from the body rolls the
+    /// transaction back. Unsuccessful responses are still rolled back straight away.
+    ///
+    /// Since the status has already been sent, the outcome is reported in an
+    /// `x-transaction-outcome` trailer, with the value `committed`, `commit-failed` or
+    /// `rolled-back`, which is declared in a `Trailer` header. Trailers are only delivered over
+    /// HTTP/2, or HTTP/1.1 if the client sends `TE: trailers`. The
+    /// [`constraint_classifier`](Config::constraint_classifier) can't be applied to commit failures
+    /// in this mode.
+    pub fn resolve_on_body_end(mut self, enabled: bool) -> Self {
+        self.resolve_on_body_end = enabled;
+        self
+    }
+
+    /// Inspect the body of successful responses before committing.
+    ///
+    /// When set, the body of responses that would otherwise be committed is buffered, up to `limit`
+    /// bytes, and passed to `classifier` to decide whether to commit (see [`BodyClassifier`]). The
+    /// buffered body is then sent as normal.
+    ///
+    /// If the body is larger than `limit`, it can't be classified, so the transaction is rolled
+    /// back and [`Event::BodyLimitExceeded`](crate::Event::BodyLimitExceeded) is reported to the
+    /// [`observer`](Config::observer). The body is still sent in full.
+    pub fn body_classifier(mut self, limit: usize, classifier: impl BodyClassifier) -> Self {
+        self.body_classifier = Some((limit, Box::new(classifier)));
+        self
+    }
+
     /// Create a [`State`] and [`Layer`] to enable the [`Tx`](crate::Tx) extractor.
     pub fn setup(self) -> (State<DB>, Layer<DB, LayerError>) {
-        let state = State::new(self.pool);
+        let admission = if self.max_transactions.is_some() || self.reserved.iter().any(|n| *n > 0) {
+            let limit = self
+                .max_transactions
+                .unwrap_or(self.pool.options().get_max_connections() as usize);
+            Some(Admission::new(limit, self.reserved, self.saturation))
+        } else {
+            None
+        };
+        let options = Options {
+            retry_after: self.retry_after,
+            constraints: self.constraints,
+            admission,
+            observer: self.observer,
+            cancellation_safe: self.cancellation_safe,
+            on_panic: self.on_panic,
+            grpc: self.grpc,
+            resolve_on_body_end: self.resolve_on_body_end,
+            body_classifier: self.body_classifier,
+        };
+        let state = State::new(self.pool, options);
         let layer = Layer::new(state.clone());
         (state, layer)
     }
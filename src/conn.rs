@@ -0,0 +1,165 @@
+//! A request extension that enables the [`Conn`](crate::Conn) extractor.
+
+use std::{fmt, marker::PhantomData, panic::Location};
+
+use axum_core::{
+    extract::{FromRef, FromRequestParts},
+    response::IntoResponse,
+};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use http::request::Parts;
+use sqlx::pool::PoolConnection;
+
+use crate::{
+    extension::{Extension, Guard, LazyConnection},
+    Error, Marker, State,
+};
+
+/// An `axum` extractor for a plain database connection, without a transaction.
+///
+/// This is useful for statements that can't run inside a transaction (e.g.
+/// `CREATE INDEX CONCURRENTLY` or `VACUUM`), or for endpoints that only run a single autocommit
+/// statement. Like [`Tx`](crate::Tx), `Conn` is enabled by the [`Layer`](crate::Layer): a
+/// connection is acquired from the pool the first time the extractor is used for a request, the
+/// same connection is returned for subsequent uses of `Conn` on the same request, and the
+/// connection is returned to the pool once the inner service responds.
+///
+/// `&mut Conn` implements [`sqlx::Executor`], and `Conn` implements
+/// `Deref<Target = `[`sqlx::pool::PoolConnection`]`>` and `DerefMut`:
+///
+/// ```
+/// use axum_sqlx_tx::Conn;
+/// use sqlx::Sqlite;
+///
+/// async fn vacuum(mut conn: Conn<Sqlite>) -> Result<(), sqlx::Error> {
+///     sqlx::query("VACUUM").execute(&mut conn).await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The connection is separate from the request's transaction, so changes made through `Conn` are
+/// committed immediately, regardless of the response. `Conn` doesn't count towards the
+/// [transaction limit](crate::Config::max_transactions), and it can't be extracted once
+/// [draining](State::drain) has started.
+///
+/// The `E` generic parameter controls the error type returned when the extractor fails, as for
+/// [`Tx`](crate::Tx).
+pub struct Conn<DB: Marker, E = Error> {
+    conn: Guard<LazyConnection<DB>>,
+    _error: PhantomData<E>,
+}
+
+impl<DB: Marker, E> fmt::Debug for Conn<DB, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Conn").finish_non_exhaustive()
+    }
+}
+
+impl<DB: Marker, E> AsRef<PoolConnection<DB::Driver>> for Conn<DB, E> {
+    fn as_ref(&self) -> &PoolConnection<DB::Driver> {
+        self.conn.as_ref()
+    }
+}
+
+impl<DB: Marker, E> AsMut<PoolConnection<DB::Driver>> for Conn<DB, E> {
+    fn as_mut(&mut self) -> &mut PoolConnection<DB::Driver> {
+        self.conn.as_mut()
+    }
+}
+
+impl<DB: Marker, E> std::ops::Deref for Conn<DB, E> {
+    type Target = PoolConnection<DB::Driver>;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref()
+    }
+}
+
+impl<DB: Marker, E> std::ops::DerefMut for Conn<DB, E> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut()
+    }
+}
+
+impl<DB: Marker, S, E> FromRequestParts<S> for Conn<DB, E>
+where
+    S: Sync,
+    E: From<Error> + IntoResponse + Send,
+    State<DB>: FromRef<S>,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ext: &Extension<DB> = parts.extensions.get().ok_or(Error::MissingExtension)?;
+
+        let conn = ext.connection(Location::caller()).await?;
+
+        Ok(Self {
+            conn,
+            _error: PhantomData,
+        })
+    }
+}
+
+impl<'c, DB, E> sqlx::Executor<'c> for &'c mut Conn<DB, E>
+where
+    DB: Marker,
+    for<'t> &'t mut <DB::Driver as sqlx::Database>::Connection:
+        sqlx::Executor<'t, Database = DB::Driver>,
+    E: std::fmt::Debug + Send,
+{
+    type Database = DB::Driver;
+
+    #[allow(clippy::type_complexity)]
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<
+        'e,
+        Result<
+            sqlx::Either<
+                <Self::Database as sqlx::Database>::QueryResult,
+                <Self::Database as sqlx::Database>::Row,
+            >,
+            sqlx::Error,
+        >,
+    >
+    where
+        'c: 'e,
+        Q: sqlx::Execute<'q, Self::Database> + 'q,
+    {
+        (&mut ***self).fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxFuture<'e, Result<Option<<Self::Database as sqlx::Database>::Row>, sqlx::Error>>
+    where
+        'c: 'e,
+        Q: sqlx::Execute<'q, Self::Database> + 'q,
+    {
+        (&mut ***self).fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [<Self::Database as sqlx::Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Self::Database as sqlx::Database>::Statement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut ***self).prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<sqlx::Describe<Self::Database>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut ***self).describe(sql)
+    }
+}
@@ -0,0 +1,164 @@
+use axum_core::response::{IntoResponse, Response};
+use bytes::Bytes;
+use http::StatusCode;
+use sqlx::error::ErrorKind;
+
+/// A kind of database constraint violation.
+///
+/// See [`ConstraintClassifier`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Constraint {
+    /// A unique or primary key constraint was violated.
+    Unique,
+    /// A foreign key constraint was violated.
+    ForeignKey,
+    /// A not-null constraint was violated.
+    NotNull,
+    /// A check constraint was violated.
+    Check,
+}
+
+impl Constraint {
+    /// Determine which constraint, if any, was violated to cause `error`.
+    pub fn of(error: &sqlx::Error) -> Option<Self> {
+        match error.as_database_error()?.kind() {
+            ErrorKind::UniqueViolation => Some(Self::Unique),
+            ErrorKind::ForeignKeyViolation => Some(Self::ForeignKey),
+            ErrorKind::NotNullViolation => Some(Self::NotNull),
+            ErrorKind::CheckViolation => Some(Self::Check),
+            _ => None,
+        }
+    }
+}
+
+/// Maps database constraint violations to responses.
+///
+/// By default, constraint violations are reported like any other database error (e.g. HTTP 500
+/// for [`Error`](crate::Error)). Setting a classifier with
+/// [`Config::constraint_classifier`](crate::Config::constraint_classifier) changes the response for
+/// violations it recognises:
+///
+/// - Violations while committing the transaction (e.g. an unsatisfied deferred constraint).
+/// - Errors returned by handlers as [`Error`](crate::Error) (or
+#[cfg_attr(
+    feature = "problem-json",
+    doc = "  [`Problem`](crate::problem::Problem),"
+)]
+#[cfg_attr(not(feature = "problem-json"), doc = "  `Problem`,")]
+///   with the `problem-json` feature) that were caused by a constraint violation, e.g. when
+///   using `?` on the result of a query.
+///
+/// The default classifier maps unique violations to HTTP 409, and foreign key and check violations
+/// to HTTP 422. The body of each response is the canonical reason for the status code, unless
+/// overridden.
+///
+/// ```
+/// use axum_sqlx_tx::{Constraint, ConstraintClassifier};
+/// use http::StatusCode;
+///
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// let classifier = ConstraintClassifier::default()
+///     .map(Constraint::Unique, StatusCode::CONFLICT, "already exists")
+///     .map(Constraint::NotNull, StatusCode::UNPROCESSABLE_ENTITY, "missing value")
+///     .ignore(Constraint::Check);
+///
+/// let (state, layer) = Tx::config(pool).constraint_classifier(classifier).setup();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConstraintClassifier {
+    unique: Option<Rule>,
+    foreign_key: Option<Rule>,
+    not_null: Option<Rule>,
+    check: Option<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    status: StatusCode,
+    body: Bytes,
+}
+
+impl ConstraintClassifier {
+    /// A classifier that doesn't map any constraint violations.
+    pub fn empty() -> Self {
+        Self {
+            unique: None,
+            foreign_key: None,
+            not_null: None,
+            check: None,
+        }
+    }
+
+    /// Respond with `status` and `body` when `constraint` is violated.
+    pub fn map(
+        mut self,
+        constraint: Constraint,
+        status: StatusCode,
+        body: impl Into<Bytes>,
+    ) -> Self {
+        *self.rule_mut(constraint) = Some(Rule {
+            status,
+            body: body.into(),
+        });
+        self
+    }
+
+    /// Don't map violations of `constraint`.
+    pub fn ignore(mut self, constraint: Constraint) -> Self {
+        *self.rule_mut(constraint) = None;
+        self
+    }
+
+    pub(crate) fn respond(&self, constraint: Constraint) -> Option<Response> {
+        let rule = match constraint {
+            Constraint::Unique => &self.unique,
+            Constraint::ForeignKey => &self.foreign_key,
+            Constraint::NotNull => &self.not_null,
+            Constraint::Check => &self.check,
+        };
+        rule.as_ref()
+            .map(|rule| (rule.status, rule.body.clone()).into_response())
+    }
+
+    fn rule_mut(&mut self, constraint: Constraint) -> &mut Option<Rule> {
+        match constraint {
+            Constraint::Unique => &mut self.unique,
+            Constraint::ForeignKey => &mut self.foreign_key,
+            Constraint::NotNull => &mut self.not_null,
+            Constraint::Check => &mut self.check,
+        }
+    }
+}
+
+impl Default for ConstraintClassifier {
+    fn default() -> Self {
+        let canonical = |status: StatusCode| status.canonical_reason().unwrap_or_default();
+        Self::empty()
+            .map(
+                Constraint::Unique,
+                StatusCode::CONFLICT,
+                canonical(StatusCode::CONFLICT),
+            )
+            .map(
+                Constraint::ForeignKey,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                canonical(StatusCode::UNPROCESSABLE_ENTITY),
+            )
+            .map(
+                Constraint::Check,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                canonical(StatusCode::UNPROCESSABLE_ENTITY),
+            )
+    }
+}
+
+/// Response extension recording the constraint violation behind an error response.
+///
+/// This lets the [`Layer`](crate::Layer) apply the configured [`ConstraintClassifier`] to errors
+/// returned from handlers.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Violation(pub(crate) Constraint);
@@ -1,3 +1,13 @@
+use std::time::Duration;
+
+use http::{header::RETRY_AFTER, HeaderValue, StatusCode};
+
+use crate::{
+    constraint::Violation,
+    transactional::{is_retryable, Retryable},
+    Constraint,
+};
+
 /// Possible errors when extracting [`Tx`] from a request.
 ///
 /// Errors can occur at two points during the request lifecycle:
@@ -7,16 +17,29 @@
 ///
 ///    - Forgetting to add the middleware: [`Error::MissingExtension`].
 ///    - Calling the extractor multiple times in the same request: [`Error::OverlappingExtractors`].
-///    - A problem communicating with the database: [`Error::Database`].
+///    - The pool being exhausted or shut down: [`Error::PoolTimedOut`] and [`Error::PoolClosed`].
+///    - Too many concurrent transactions, if limited: [`Error::Saturated`].
+///    - The application shutting down: [`Error::Draining`].
+///    - A problem communicating with the database: [`Error::Begin`] (or [`Error::Acquire`], for
+///      the [`Conn`](crate::Conn) extractor).
 ///
 /// 2. The middleware [`Layer`] might fail to commit the transaction. This could be due to a problem
 ///    communicating with the database, or else a logic error (e.g. unsatisfied deferred
-///    constraint): [`Error::Database`].
+///    constraint): [`Error::Commit`]. If configured to do so, the middleware will also respond with
+///    [`Error::Panicked`] when the handler panics. [`Transactional`](crate::Transactional)
+///    handlers commit the transaction themselves, and can also fail with [`Error::TimedOut`], or
+///    with [`Error::AlreadyBegun`] if the transaction was begun before them.
+///
+/// `Error` also implements `From<sqlx::Error>`, so it can be used as the error type of handlers
+/// that run queries. Such errors are reported as [`Error::Query`] (or [`Error::PoolTimedOut`] and
+/// [`Error::PoolClosed`], if that's what they are).
 ///
 /// `axum` requires that errors can be turned into responses. The [`Error`] type converts into a
-/// HTTP 500 response with the error message as the response body. This may be suitable for
-/// development or internal services but it's generally not advisable to return internal error
-/// details to clients.
+/// response with the status given by [`Error::status`] and the error message as the response body.
+/// Pool exhaustion is reported as HTTP 503 with a `Retry-After` header, if one was configured with
+/// [`Config::retry_after`](crate::Config::retry_after), so that clients and load balancers can back
+/// off. Everything else is reported as HTTP 500. This may be suitable for development or internal
+/// services but it's generally not advisable to return internal error details to clients.
 ///
 /// You can override the error types for both the [`Tx`] extractor and [`Layer`]:
 ///
@@ -24,28 +47,19 @@
 ///   convertible from [`Error`] (e.g. [`Error`]`: Into<E>`).
 ///
 /// - Override the [`Layer`] error type using [`Config::layer_error`](crate::Config::layer_error).
-///   The layer error type must be convertible from `sqlx::Error` (e.g.
-///   `sqlx::Error: Into<LayerError>`).
+///   The layer error type must also be convertible from [`Error`] (e.g.
+///   [`Error`]`: Into<LayerError>`).
 ///
 /// In both cases, the error type must implement `axum::response::IntoResponse`.
 ///
 /// ```
 /// use axum::{response::IntoResponse, routing::post};
 ///
-/// enum MyError{
-///     Extractor(axum_sqlx_tx::Error),
-///     Layer(sqlx::Error),
-/// }
+/// struct MyError(axum_sqlx_tx::Error);
 ///
 /// impl From<axum_sqlx_tx::Error> for MyError {
 ///     fn from(error: axum_sqlx_tx::Error) -> Self {
-///         Self::Extractor(error)
-///     }
-/// }
-///
-/// impl From<sqlx::Error> for MyError {
-///     fn from(error: sqlx::Error) -> Self {
-///         Self::Layer(error)
+///         Self(error)
 ///     }
 /// }
 ///
@@ -81,6 +95,7 @@
 /// [`Tx`]: crate::Tx
 /// [`Layer`]: crate::Layer
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
     /// Indicates that the [`Layer`](crate::Layer) middleware was not installed.
     #[error("required extension not registered; did you add the axum_sqlx_tx::Layer middleware?")]
@@ -88,19 +103,206 @@ pub enum Error {
 
     /// Indicates that [`Tx`](crate::Tx) was extracted multiple times in a single
     /// handler/middleware.
-    #[error("axum_sqlx_tx::Tx extractor used multiple times in the same handler/middleware")]
-    OverlappingExtractors,
+    ///
+    /// In debug builds, `holder` is where the transaction was locked: the call to
+    /// [`Tx::from_extensions`](crate::Tx::from_extensions), or the extractor or middleware in this
+    /// crate that holds it (e.g. [`Conn`] or [`Loaded`]). It's `None` in release builds, or once
+    /// the transaction has been released or committed.
+    ///
+    /// [`Conn`]: crate::Conn
+    /// [`Loaded`]: crate::Loaded
+    #[error(
+        "axum_sqlx_tx::Tx extractor used multiple times in the same handler/middleware{}",
+        held_by(.holder)
+    )]
+    OverlappingExtractors {
+        holder: Option<&'static std::panic::Location<'static>>,
+    },
 
-    /// A database error occurred when starting or committing the transaction.
-    #[error(transparent)]
-    Database {
-        #[from]
+    /// Indicates that the [`Layer`](crate::Layer) was applied more than once for the same database,
+    /// with a different [`State`](crate::State) each time.
+    ///
+    /// A layer applied more than once with the same `State` (e.g. to both a router and a nested
+    /// router) reuses the outer layer's transaction, but layers with different pools or
+    /// configuration can't share one.
+    #[error(
+        "axum_sqlx_tx::Layer applied more than once for the same database with different State; \
+         use the same State for nested layers"
+    )]
+    ConflictingLayers,
+
+    /// Indicates that the request's transaction had already begun (e.g. in middleware) when a
+    /// [`Transactional`](crate::Transactional) handler ran, so it couldn't be begun with the
+    /// handler's options.
+    #[error(
+        "transaction already begun before axum_sqlx_tx::Transactional::run; don't use Tx before \
+         Transactional handlers"
+    )]
+    AlreadyBegun,
+
+    /// Indicates that no connection became available before the pool's acquire timeout elapsed.
+    #[error("timed out waiting for a database connection")]
+    PoolTimedOut {
+        /// How long clients should wait before retrying, as configured by
+        /// [`Config::retry_after`](crate::Config::retry_after).
+        retry_after: Option<Duration>,
+    },
+
+    /// Indicates that the [transaction limit](crate::Config::max_transactions) was reached.
+    #[error("too many concurrent transactions")]
+    Saturated {
+        /// How long clients should wait before retrying, as configured by
+        /// [`Config::retry_after`](crate::Config::retry_after).
+        retry_after: Option<Duration>,
+    },
+
+    /// Indicates that the application is shutting down, and no new transactions can be begun.
+    ///
+    /// See [`State::drain`](crate::State::drain).
+    #[error("not accepting new transactions while shutting down")]
+    Draining,
+
+    /// Indicates that the pool has been closed.
+    #[error("database pool is closed")]
+    PoolClosed,
+
+    /// A database error occurred when acquiring a connection for [`Conn`](crate::Conn).
+    #[error("failed to acquire connection: {error}")]
+    Acquire {
+        #[source]
+        error: sqlx::Error,
+    },
+
+    /// A database error occurred when starting the transaction.
+    #[error("failed to begin transaction: {error}")]
+    Begin {
+        #[source]
+        error: sqlx::Error,
+    },
+
+    /// A database error occurred when committing the transaction.
+    #[error("failed to commit transaction: {error}")]
+    Commit {
+        #[source]
         error: sqlx::Error,
     },
+
+    /// A database error occurred when rolling back the transaction with
+    /// [`Tx::rollback_and_continue`](crate::Tx::rollback_and_continue).
+    #[error("failed to roll back transaction: {error}")]
+    Rollback {
+        #[source]
+        error: sqlx::Error,
+    },
+
+    /// Indicates that the inner service panicked, and the transaction was rolled back.
+    ///
+    /// This is only returned if [`Config::on_panic`](crate::Config::on_panic) is set to
+    /// [`OnPanic::Respond`](crate::OnPanic::Respond).
+    #[error("the request handler panicked; the transaction was rolled back")]
+    Panicked,
+
+    /// Indicates that a [`Transactional`](crate::Transactional) handler didn't complete within
+    /// its timeout, and the transaction was rolled back.
+    #[error("the request handler timed out; the transaction was rolled back")]
+    TimedOut,
+
+    /// A database error occurred when running a query.
+    #[error(transparent)]
+    Query { error: sqlx::Error },
+}
+
+impl Error {
+    /// The HTTP status code used when converting the error into a response.
+    ///
+    /// This is `503 Service Unavailable` for [`Error::PoolTimedOut`], [`Error::Saturated`],
+    /// [`Error::Draining`], [`Error::PoolClosed`] and [`Error::TimedOut`], and
+    /// `500 Internal Server Error` otherwise.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::PoolTimedOut { .. }
+            | Self::Saturated { .. }
+            | Self::Draining
+            | Self::PoolClosed
+            | Self::TimedOut => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The database constraint whose violation caused the error, if any.
+    pub fn constraint(&self) -> Option<Constraint> {
+        match self {
+            Self::Begin { error } | Self::Commit { error } | Self::Query { error } => {
+                Constraint::of(error)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the error was caused by a serialization failure or deadlock, so that retrying the
+    /// transaction may succeed.
+    ///
+    /// See [`Transactional::retry`](crate::Transactional::retry).
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Begin { error } | Self::Commit { error } | Self::Query { error } => {
+                is_retryable(error)
+            }
+            _ => false,
+        }
+    }
+
+    /// How long clients should wait before retrying the request, if known.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::PoolTimedOut { retry_after } | Self::Saturated { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::PoolTimedOut => Self::PoolTimedOut { retry_after: None },
+            sqlx::Error::PoolClosed => Self::PoolClosed,
+            // An error from beginning a lazy transaction, see `Tx::ensure_begun_for_query`
+            sqlx::Error::Configuration(error) => match error.downcast::<Self>() {
+                Ok(error) => *error,
+                Err(error) => Self::Query {
+                    error: sqlx::Error::Configuration(error),
+                },
+            },
+            error => Self::Query { error },
+        }
+    }
 }
 
 impl axum_core::response::IntoResponse for Error {
     fn into_response(self) -> axum_core::response::Response {
-        (http::StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+        let status = self.status();
+        let mut res = (status, self.to_string()).into_response();
+        if let Some(retry_after) = self.retry_after() {
+            res.headers_mut()
+                .insert(RETRY_AFTER, retry_after_header(retry_after));
+        }
+        if let Some(constraint) = self.constraint() {
+            res.extensions_mut().insert(Violation(constraint));
+        }
+        if self.is_retryable() {
+            res.extensions_mut().insert(Retryable);
+        }
+        res
     }
 }
+
+/// Format a `Retry-After` header value as a whole number of seconds, rounding up.
+pub(crate) fn retry_after_header(duration: Duration) -> HeaderValue {
+    let secs = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    HeaderValue::from(secs)
+}
+
+/// Describe the holder of an overlapping extractor, for [`Error::OverlappingExtractors`].
+fn held_by(holder: &Option<&'static std::panic::Location<'static>>) -> String {
+    holder.map_or_else(String::new, |holder| format!(" (held by {holder})"))
+}
@@ -1,49 +1,243 @@
-use std::sync::Arc;
+use std::{
+    panic::Location,
+    sync::{Arc, Weak},
+    time::Instant,
+};
 
 use parking_lot::{lock_api::ArcMutexGuard, Mutex, RawMutex};
-use sqlx::Transaction;
+use sqlx::{pool::PoolConnection, TransactionManager as _};
 
-use crate::{Error, Marker, State};
+use crate::{admission::Permit, registry::Registration, Error, Marker, Priority, State, TxOptions};
 
 /// The request extension.
 pub(crate) struct Extension<DB: Marker> {
-    slot: Arc<Mutex<LazyTransaction<DB>>>,
+    state: State<DB>,
+    slot: Lock<LazyTransaction<DB>>,
+    conn: Lock<LazyConnection<DB>>,
 }
 
 impl<DB: Marker> Extension<DB> {
     pub(crate) fn new(state: State<DB>) -> Self {
-        let slot = Arc::new(Mutex::new(LazyTransaction::new(state)));
-        Self { slot }
+        let conn = Lock::new(LazyConnection::new(state.clone()));
+        let slot = Lock::new(LazyTransaction::new(state.clone()));
+        Self { state, slot, conn }
+    }
+
+    pub(crate) fn state(&self) -> &State<DB> {
+        &self.state
+    }
+
+    /// Lock the transaction without beginning it (see `LazyTx`).
+    ///
+    /// `holder` is where the lock is being taken, for [`Error::OverlappingExtractors`].
+    pub(crate) fn lock(
+        &self,
+        holder: &'static Location<'static>,
+    ) -> Result<Guard<LazyTransaction<DB>>, Error> {
+        self.slot.lock(holder)
     }
 
     pub(crate) async fn acquire(
         &self,
-    ) -> Result<ArcMutexGuard<RawMutex, LazyTransaction<DB>>, Error> {
-        let mut tx = self
-            .slot
-            .try_lock_arc()
-            .ok_or(Error::OverlappingExtractors)?;
-        tx.acquire().await?;
+        priority: Priority,
+        options: TxOptions,
+        holder: &'static Location<'static>,
+    ) -> Result<Guard<LazyTransaction<DB>>, Error> {
+        let mut tx = self.lock(holder)?;
+        tx.acquire(priority, options, self.slot.downgrade()).await?;
 
         Ok(tx)
     }
 
+    pub(crate) async fn connection(
+        &self,
+        holder: &'static Location<'static>,
+    ) -> Result<Guard<LazyConnection<DB>>, Error> {
+        let mut conn = self.conn.lock(holder)?;
+        conn.acquire().await?;
+
+        Ok(conn)
+    }
+
+    /// Whether the transaction has begun. A transaction that's in use isn't checked, since it can't
+    /// be extracted again anyway.
+    pub(crate) fn is_acquired(&self) -> bool {
+        self.slot
+            .value
+            .try_lock()
+            .is_some_and(|tx| tx.is_acquired())
+    }
+
+    /// Return the connection to the pool, if one was acquired and isn't in use.
+    pub(crate) fn release_connection(&self) {
+        if let Ok(mut conn) = self.conn.lock(Location::caller()) {
+            conn.0 = LazyConnectionState::Released;
+        }
+    }
+
     pub(crate) async fn resolve(&self) -> Result<(), sqlx::Error> {
-        if let Some(mut tx) = self.slot.try_lock_arc() {
+        if let Ok(mut tx) = self.slot.lock(Location::caller()) {
             tx.resolve().await?;
         }
         Ok(())
     }
+
+    /// Roll back the transaction, or return `None` if it's in use (e.g. by a `Tx` that was moved
+    /// into a spawned task).
+    pub(crate) async fn rollback(&self) -> Option<Result<(), sqlx::Error>> {
+        let mut tx = self.slot.lock(Location::caller()).ok()?;
+        Some(tx.rollback().await)
+    }
+
+    /// Commit or roll back the transaction before the response is returned, leaving it unacquired
+    /// so that it can be begun again (or left for the `Layer`, which finds nothing to resolve).
+    pub(crate) async fn resolve_and_continue(&self, commit: bool) -> Result<(), sqlx::Error> {
+        if let Ok(mut tx) = self.slot.lock(Location::caller()) {
+            tx.resolve_and_continue(commit).await?;
+        }
+        Ok(())
+    }
 }
 
 impl<DB: Marker> Clone for Extension<DB> {
     fn clone(&self) -> Self {
         Self {
+            state: self.state.clone(),
             slot: self.slot.clone(),
+            conn: self.conn.clone(),
+        }
+    }
+}
+
+/// A shared value that can only be locked once at a time, which records where it was locked (in
+/// debug builds) for [`Error::OverlappingExtractors`].
+pub(crate) struct Lock<T> {
+    value: Arc<Mutex<T>>,
+    holder: Holder,
+}
+
+impl<T> Lock<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value: Arc::new(Mutex::new(value)),
+            holder: Holder::default(),
+        }
+    }
+
+    /// Lock the value on behalf of `holder`, or fail with the location of the current holder.
+    pub(crate) fn lock(&self, holder: &'static Location<'static>) -> Result<Guard<T>, Error> {
+        let guard = self
+            .value
+            .try_lock_arc()
+            .ok_or_else(|| self.holder.overlapping())?;
+        self.holder.set(Some(holder));
+        Ok(Guard {
+            guard,
+            holder: self.holder.clone(),
+        })
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakLock<T> {
+        WeakLock {
+            value: Arc::downgrade(&self.value),
+            holder: self.holder.clone(),
         }
     }
 }
 
+impl<T> Clone for Lock<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            holder: self.holder.clone(),
+        }
+    }
+}
+
+/// A [`Lock`] that doesn't keep its value alive.
+pub(crate) struct WeakLock<T> {
+    value: Weak<Mutex<T>>,
+    holder: Holder,
+}
+
+impl<T> WeakLock<T> {
+    pub(crate) fn upgrade(&self) -> Option<Lock<T>> {
+        Some(Lock {
+            value: self.value.upgrade()?,
+            holder: self.holder.clone(),
+        })
+    }
+}
+
+/// A locked [`Lock`], which clears the recorded holder when it's dropped.
+pub(crate) struct Guard<T> {
+    guard: ArcMutexGuard<RawMutex, T>,
+    holder: Holder,
+}
+
+impl<T> Guard<T> {
+    /// The lock this guard holds.
+    pub(crate) fn downgrade(this: &Self) -> WeakLock<T> {
+        WeakLock {
+            value: Arc::downgrade(ArcMutexGuard::mutex(&this.guard)),
+            holder: this.holder.clone(),
+        }
+    }
+}
+
+impl<T> std::ops::Deref for Guard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> std::ops::DerefMut for Guard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for Guard<T> {
+    fn drop(&mut self) {
+        self.holder.set(None);
+    }
+}
+
+/// Where the holder of a lock took it, recorded in debug builds for
+/// [`Error::OverlappingExtractors`].
+#[derive(Clone, Default)]
+struct Holder {
+    #[cfg(debug_assertions)]
+    location: Arc<Mutex<Option<&'static Location<'static>>>>,
+}
+
+impl Holder {
+    fn set(&self, location: Option<&'static Location<'static>>) {
+        #[cfg(debug_assertions)]
+        {
+            *self.location.lock() = location;
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = location;
+    }
+
+    fn overlapping(&self) -> Error {
+        #[cfg(debug_assertions)]
+        let holder = *self.location.lock();
+        #[cfg(not(debug_assertions))]
+        let holder = None;
+        Error::OverlappingExtractors { holder }
+    }
+}
+
+/// The transaction manager for `DB`, which begins and ends transactions on a connection.
+type Manager<DB> = <<DB as Marker>::Driver as sqlx::Database>::TransactionManager;
+
+/// The connection a transaction is running on.
+type Connection<DB> = <<DB as Marker>::Driver as sqlx::Database>::Connection;
+
 /// The lazy transaction.
 pub(crate) struct LazyTransaction<DB: Marker>(LazyTransactionState<DB>);
 
@@ -52,8 +246,13 @@ enum LazyTransactionState<DB: Marker> {
         state: State<DB>,
     },
     Acquired {
-        tx: Transaction<'static, DB::Driver>,
+        conn: PoolConnection<DB::Driver>,
+        state: State<DB>,
+        _permit: Option<Permit>,
+        _registration: Registration<DB>,
     },
+    /// Rolled back by [`State::drain`], so it can't be committed.
+    Aborted,
     Resolved,
 }
 
@@ -62,52 +261,228 @@ impl<DB: Marker> LazyTransaction<DB> {
         Self(LazyTransactionState::Unacquired { state })
     }
 
-    pub(crate) fn as_ref(&self) -> &Transaction<'static, DB::Driver> {
+    pub(crate) fn as_ref(&self) -> &Connection<DB> {
         match &self.0 {
             LazyTransactionState::Unacquired { .. } => {
-                panic!("BUG: exposed unacquired LazyTransaction")
+                panic!("tried to use `Tx` before the transaction began; see `Tx::ensure_begun`")
+            }
+            LazyTransactionState::Acquired { conn, .. } => conn,
+            LazyTransactionState::Aborted | LazyTransactionState::Resolved => {
+                panic!("BUG: exposed resolved LazyTransaction")
             }
-            LazyTransactionState::Acquired { tx } => tx,
-            LazyTransactionState::Resolved => panic!("BUG: exposed resolved LazyTransaction"),
         }
     }
 
-    pub(crate) fn as_mut(&mut self) -> &mut Transaction<'static, DB::Driver> {
+    pub(crate) fn as_mut(&mut self) -> &mut Connection<DB> {
         match &mut self.0 {
             LazyTransactionState::Unacquired { .. } => {
-                panic!("BUG: exposed unacquired LazyTransaction")
+                panic!("tried to use `Tx` before the transaction began; see `Tx::ensure_begun`")
+            }
+            LazyTransactionState::Acquired { conn, .. } => conn,
+            LazyTransactionState::Aborted | LazyTransactionState::Resolved => {
+                panic!("BUG: exposed resolved LazyTransaction")
             }
-            LazyTransactionState::Acquired { tx } => tx,
-            LazyTransactionState::Resolved => panic!("BUG: exposed resolved LazyTransaction"),
         }
     }
 
-    async fn acquire(&mut self) -> Result<(), Error> {
+    #[cfg(feature = "async-graphql")]
+    pub(crate) fn state(&self) -> Option<&State<DB>> {
+        match &self.0 {
+            LazyTransactionState::Unacquired { state }
+            | LazyTransactionState::Acquired { state, .. } => Some(state),
+            LazyTransactionState::Aborted | LazyTransactionState::Resolved => None,
+        }
+    }
+
+    pub(crate) fn is_acquired(&self) -> bool {
+        matches!(self.0, LazyTransactionState::Acquired { .. })
+    }
+
+    pub(crate) async fn acquire(
+        &mut self,
+        priority: Priority,
+        options: TxOptions,
+        slot: WeakLock<LazyTransaction<DB>>,
+    ) -> Result<(), Error> {
         match &self.0 {
             LazyTransactionState::Unacquired { state } => {
-                let tx = state.transaction().await?;
-                self.0 = LazyTransactionState::Acquired { tx };
+                let started = Instant::now();
+                let permit = state.admit(priority).await?;
+                let admitted = permit.is_some().then_some(started);
+                let conn = state.transaction(options, admitted).await?;
+                let registration = state.register(slot);
+                self.0 = LazyTransactionState::Acquired {
+                    conn,
+                    state: state.clone(),
+                    _permit: permit,
+                    _registration: registration,
+                };
                 Ok(())
             }
             LazyTransactionState::Acquired { .. } => Ok(()),
-            LazyTransactionState::Resolved => Err(Error::OverlappingExtractors),
+            LazyTransactionState::Aborted => Err(Error::Draining),
+            LazyTransactionState::Resolved => Err(Error::OverlappingExtractors { holder: None }),
         }
     }
 
     pub(crate) async fn resolve(&mut self) -> Result<(), sqlx::Error> {
-        match std::mem::replace(&mut self.0, LazyTransactionState::Resolved) {
+        let result = match &mut self.0 {
             LazyTransactionState::Unacquired { .. } | LazyTransactionState::Resolved => Ok(()),
-            LazyTransactionState::Acquired { tx } => tx.commit().await,
-        }
+            LazyTransactionState::Acquired { conn, .. } => commit::<DB>(conn).await,
+            LazyTransactionState::Aborted => Err(aborted()),
+        };
+        self.0 = LazyTransactionState::Resolved;
+        result
     }
 
     pub(crate) async fn commit(&mut self) -> Result<(), sqlx::Error> {
-        match std::mem::replace(&mut self.0, LazyTransactionState::Resolved) {
-            LazyTransactionState::Unacquired { .. } => {
-                panic!("BUG: tried to commit unacquired transaction")
-            }
-            LazyTransactionState::Acquired { tx } => tx.commit().await,
+        let result = match &mut self.0 {
+            // The transaction never began (see `LazyTx`), so there's nothing to commit
+            LazyTransactionState::Unacquired { .. } => Ok(()),
+            LazyTransactionState::Acquired { conn, .. } => commit::<DB>(conn).await,
+            LazyTransactionState::Aborted => Err(aborted()),
             LazyTransactionState::Resolved => panic!("BUG: tried to commit resolved transaction"),
+        };
+        self.0 = LazyTransactionState::Resolved;
+        result
+    }
+
+    /// Commit or roll back the transaction, leaving it unacquired so that a fresh transaction can
+    /// be started with [`acquire`](Self::acquire).
+    pub(crate) async fn resolve_and_continue(&mut self, commit: bool) -> Result<(), sqlx::Error> {
+        match &mut self.0 {
+            LazyTransactionState::Unacquired { .. } => Ok(()),
+            LazyTransactionState::Acquired { conn, state, .. } => {
+                let state = state.clone();
+                let result = if commit {
+                    self::commit::<DB>(conn).await
+                } else {
+                    rollback::<DB>(conn).await
+                };
+                self.0 = LazyTransactionState::Unacquired { state };
+                result
+            }
+            // Nothing can be begun while draining, so the transaction stays aborted
+            LazyTransactionState::Aborted => {
+                if commit {
+                    Err(aborted())
+                } else {
+                    Ok(())
+                }
+            }
+            LazyTransactionState::Resolved => {
+                panic!("BUG: tried to continue resolved transaction")
+            }
+        }
+    }
+
+    pub(crate) async fn rollback(&mut self) -> Result<(), sqlx::Error> {
+        let result = match &mut self.0 {
+            LazyTransactionState::Unacquired { .. }
+            | LazyTransactionState::Aborted
+            | LazyTransactionState::Resolved => Ok(()),
+            LazyTransactionState::Acquired { conn, .. } => rollback::<DB>(conn).await,
+        };
+        self.0 = LazyTransactionState::Resolved;
+        result
+    }
+
+    /// Roll back the transaction for [`State::drain`], so that trying to commit it fails rather
+    /// than reporting success for changes that were discarded.
+    pub(crate) async fn abort(&mut self) -> Result<(), sqlx::Error> {
+        let LazyTransactionState::Acquired { conn, .. } = &mut self.0 else {
+            return Ok(());
+        };
+        let result = rollback::<DB>(conn).await;
+        self.0 = LazyTransactionState::Aborted;
+        result
+    }
+}
+
+impl<DB: Marker> Drop for LazyTransaction<DB> {
+    fn drop(&mut self) {
+        // As when dropping a `sqlx::Transaction`, queue a rollback for the next time the connection
+        // is used, which includes when it's returned to the pool
+        if let LazyTransactionState::Acquired { conn, .. } = &mut self.0 {
+            Manager::<DB>::start_rollback(conn);
+        }
+    }
+}
+
+/// Commit the transaction on `conn`.
+///
+/// If that fails, a rollback is queued as it would be for a `sqlx::Transaction`, so that the
+/// connection can be reused once it's been rolled back.
+pub(crate) async fn commit<DB: Marker>(
+    conn: &mut PoolConnection<DB::Driver>,
+) -> Result<(), sqlx::Error> {
+    let result = Manager::<DB>::commit(conn).await;
+    if result.is_err() {
+        Manager::<DB>::start_rollback(conn);
+    }
+    result
+}
+
+/// Roll back the transaction on `conn`.
+///
+/// If that fails, the connection may still be in the transaction, so it's closed when it's dropped
+/// rather than being returned to the pool.
+pub(crate) async fn rollback<DB: Marker>(
+    conn: &mut PoolConnection<DB::Driver>,
+) -> Result<(), sqlx::Error> {
+    let result = Manager::<DB>::rollback(conn).await;
+    if result.is_err() {
+        conn.close_on_drop();
+    }
+    result
+}
+
+/// The error for committing a transaction that was rolled back by [`State::drain`].
+fn aborted() -> sqlx::Error {
+    sqlx::Error::Configuration(Box::new(Error::Draining))
+}
+
+/// The lazy connection.
+pub(crate) struct LazyConnection<DB: Marker>(LazyConnectionState<DB>);
+
+enum LazyConnectionState<DB: Marker> {
+    Unacquired { state: State<DB> },
+    Acquired { conn: PoolConnection<DB::Driver> },
+    Released,
+}
+
+impl<DB: Marker> LazyConnection<DB> {
+    fn new(state: State<DB>) -> Self {
+        Self(LazyConnectionState::Unacquired { state })
+    }
+
+    pub(crate) fn as_ref(&self) -> &PoolConnection<DB::Driver> {
+        match &self.0 {
+            LazyConnectionState::Acquired { conn } => conn,
+            LazyConnectionState::Unacquired { .. } | LazyConnectionState::Released => {
+                panic!("BUG: exposed unacquired LazyConnection")
+            }
+        }
+    }
+
+    pub(crate) fn as_mut(&mut self) -> &mut PoolConnection<DB::Driver> {
+        match &mut self.0 {
+            LazyConnectionState::Acquired { conn } => conn,
+            LazyConnectionState::Unacquired { .. } | LazyConnectionState::Released => {
+                panic!("BUG: exposed unacquired LazyConnection")
+            }
+        }
+    }
+
+    async fn acquire(&mut self) -> Result<(), Error> {
+        match &self.0 {
+            LazyConnectionState::Unacquired { state } => {
+                let conn = state.connection().await?;
+                self.0 = LazyConnectionState::Acquired { conn };
+                Ok(())
+            }
+            LazyConnectionState::Acquired { .. } => Ok(()),
+            LazyConnectionState::Released => Err(Error::OverlappingExtractors { holder: None }),
         }
     }
 }
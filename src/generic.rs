@@ -0,0 +1,211 @@
+//! A [`tower_layer::Layer`] that enables [`Tx`](crate::Tx) for any [`tower_service::Service`].
+//!
+//! The [`crate::Layer`] middleware only works with `axum`-style services, whose requests and
+//! responses are HTTP messages and which never fail. This module's [`Layer`] works with any
+//! service whose requests carry [`http::Extensions`] (see [`RequestExtensions`]), such as queue
+//! consumers or RPC services built on `tower`. Whether the transaction is committed is decided by
+//! an [`Outcome`] classifier, and the transaction is obtained using [`Tx::from_extensions`].
+//!
+//! Otherwise, transactions behave as they do with [`crate::Layer`], except for options that only
+//! make sense for HTTP responses: the [`ConstraintClassifier`](crate::ConstraintClassifier) isn't
+//! applied, panics are always resumed, and failing to commit results in the inner service's error
+//! type converted from [`Error::Commit`].
+//!
+//! ```
+//! use axum_sqlx_tx::generic::{Layer, RequestExtensions};
+//! use tower::ServiceBuilder;
+//!
+//! type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+//!
+//! struct Job {
+//!     extensions: http::Extensions,
+//!     /* ... */
+//! }
+//!
+//! impl RequestExtensions for Job {
+//!     fn extensions(&self) -> &http::Extensions {
+//!         &self.extensions
+//!     }
+//!
+//!     fn extensions_mut(&mut self) -> &mut http::Extensions {
+//!         &mut self.extensions
+//!     }
+//! }
+//!
+//! # async fn foo() {
+//! # let pool: sqlx::SqlitePool = todo!();
+//! let (state, _) = Tx::setup(pool);
+//!
+//! let consumer = ServiceBuilder::new()
+//!     // Commit unless the job failed
+//!     .layer(Layer::new(state, |result: &Result<(), axum_sqlx_tx::Error>| {
+//!         result.is_ok()
+//!     }))
+//!     .service_fn(|job: Job| async move {
+//!         let mut tx = Tx::from_extensions(job.extensions()).await?;
+//!         sqlx::query("...").execute(&mut tx).await?;
+//!         Ok::<_, axum_sqlx_tx::Error>(())
+//!     });
+//! # }
+//! ```
+//!
+//! [`Tx::from_extensions`]: crate::Tx::from_extensions
+
+use futures_core::future::BoxFuture;
+
+use crate::{
+    extension::Extension,
+    layer::{finish, rollback_after_panic, Guarded},
+    Error, Marker, State,
+};
+
+/// A request type that carries [`http::Extensions`].
+pub trait RequestExtensions {
+    /// The request's extensions.
+    fn extensions(&self) -> &http::Extensions;
+
+    /// The request's extensions, mutably.
+    fn extensions_mut(&mut self) -> &mut http::Extensions;
+}
+
+impl<B> RequestExtensions for http::Request<B> {
+    fn extensions(&self) -> &http::Extensions {
+        self.extensions()
+    }
+
+    fn extensions_mut(&mut self) -> &mut http::Extensions {
+        self.extensions_mut()
+    }
+}
+
+/// Decides whether a request's transaction should be committed, based on the inner service's
+/// result.
+///
+/// `Outcome` is implemented for closures taking a `&Result<Res, Err>` and returning `bool`.
+pub trait Outcome<Res, Err>: Clone + Send + Sync + 'static {
+    /// Returns `true` if the transaction should be committed, or `false` to roll it back.
+    fn commit(&self, result: &Result<Res, Err>) -> bool;
+}
+
+impl<F, Res, Err> Outcome<Res, Err> for F
+where
+    F: Fn(&Result<Res, Err>) -> bool + Clone + Send + Sync + 'static,
+{
+    fn commit(&self, result: &Result<Res, Err>) -> bool {
+        self(result)
+    }
+}
+
+/// A [`tower_layer::Layer`] that enables [`Tx`](crate::Tx) for any [`tower_service::Service`].
+///
+/// See the [module documentation](self) for more information.
+pub struct Layer<DB: Marker, O> {
+    state: State<DB>,
+    outcome: O,
+}
+
+impl<DB: Marker, O> Layer<DB, O> {
+    /// Create a layer using the given [`State`] and [`Outcome`] classifier.
+    ///
+    /// The `State` can be shared with an [`axum` layer](crate::Layer), e.g. by calling
+    /// [`Config::setup`](crate::Config::setup) and using the returned `State`.
+    pub fn new(state: State<DB>, outcome: O) -> Self {
+        Self { state, outcome }
+    }
+}
+
+impl<DB: Marker, O: Clone> Clone for Layer<DB, O> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            outcome: self.outcome.clone(),
+        }
+    }
+}
+
+impl<DB: Marker, S, O: Clone> tower_layer::Layer<S> for Layer<DB, O> {
+    type Service = Service<DB, S, O>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            state: self.state.clone(),
+            inner,
+            outcome: self.outcome.clone(),
+        }
+    }
+}
+
+/// A [`tower_service::Service`] that enables [`Tx`](crate::Tx) for any inner service.
+///
+/// See [`Layer`] for more information.
+pub struct Service<DB: Marker, S, O> {
+    state: State<DB>,
+    inner: S,
+    outcome: O,
+}
+
+// can't simply derive because `DB` isn't `Clone`
+impl<DB: Marker, S: Clone, O: Clone> Clone for Service<DB, S, O> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            inner: self.inner.clone(),
+            outcome: self.outcome.clone(),
+        }
+    }
+}
+
+impl<DB: Marker, S, O, Req> tower_service::Service<Req> for Service<DB, S, O>
+where
+    S: tower_service::Service<Req>,
+    S::Future: Send + 'static,
+    S::Response: Send + 'static,
+    S::Error: From<Error> + Send + 'static,
+    O: Outcome<S::Response, S::Error>,
+    Req: RequestExtensions,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Req) -> Self::Future {
+        // Reuse the transaction of an outer layer for the same database, see `crate::Layer`
+        if let Some(outer) = req.extensions().get::<Extension<DB>>() {
+            if !outer.state().is_same(&self.state) {
+                return Box::pin(async { Err(Error::ConflictingLayers.into()) });
+            }
+            return Box::pin(self.inner.call(req));
+        }
+
+        let state = self.state.clone();
+        let outcome = self.outcome.clone();
+        let ext = Extension::new(state.clone());
+        req.extensions_mut().insert(ext.clone());
+
+        let res = Guarded::new(self.inner.call(req), &ext, &state);
+
+        Box::pin(async move {
+            let res = match res.await {
+                Ok(res) => res,
+                Err(panic) => {
+                    // There's no response to respond with, so always resume
+                    let panic = rollback_after_panic(&ext, &state, panic).await;
+                    std::panic::resume_unwind(panic)
+                }
+            };
+
+            let commit = outcome.commit(&res);
+            match finish(ext, &state, commit).await {
+                Ok(()) => res,
+                Err(error) => Err(Error::Commit { error }.into()),
+            }
+        })
+    }
+}
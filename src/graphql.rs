@@ -0,0 +1,157 @@
+//! [`async-graphql`] integration, sharing the request's transaction across resolvers.
+//!
+//! A GraphQL request runs many resolvers, which can't each use the [`Tx`] extractor. Instead, the
+//! handler extracts the `Tx` once and executes the request with [`SharedTx::execute`], which makes
+//! a [`SharedTx`] handle available to resolvers as context data. Resolvers take turns using the
+//! transaction by [locking](SharedTx::lock) the handle.
+//!
+//! GraphQL responds with HTTP 200 even when resolvers fail, so `SharedTx::execute` rolls the
+//! transaction back if the response contains errors (or a resolver asked for it with
+//! [`SharedTx::set_rollback_only`]). Otherwise, the [`Layer`](crate::Layer) commits it as usual.
+//!
+//! ```
+//! use async_graphql::{Context, EmptySubscription, Object, Schema};
+//! use axum::{Extension, Json};
+//! use axum_sqlx_tx::graphql::SharedTx;
+//!
+//! type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+//!
+//! struct Query;
+//!
+//! #[Object]
+//! impl Query {
+//!     async fn users(&self, ctx: &Context<'_>) -> async_graphql::Result<i64> {
+//!         let mut tx = ctx.data::<SharedTx<sqlx::Sqlite>>()?.lock().await;
+//!         let (count,) = sqlx::query_as("SELECT COUNT(*) FROM users")
+//!             .fetch_one(&mut *tx)
+//!             .await?;
+//!         Ok(count)
+//!     }
+//! }
+//!
+//! struct Mutation;
+//!
+//! #[Object]
+//! impl Mutation {
+//!     async fn create_user(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<bool> {
+//!         let mut tx = ctx.data::<SharedTx<sqlx::Sqlite>>()?.lock().await;
+//!         sqlx::query("INSERT INTO users (name) VALUES (?)")
+//!             .bind(name)
+//!             .execute(&mut *tx)
+//!             .await?;
+//!         Ok(true)
+//!     }
+//! }
+//!
+//! type AppSchema = Schema<Query, Mutation, EmptySubscription>;
+//!
+//! async fn graphql(
+//!     Extension(schema): Extension<AppSchema>,
+//!     tx: Tx,
+//!     Json(request): Json<async_graphql::Request>,
+//! ) -> Json<async_graphql::BatchResponse> {
+//!     Json(SharedTx::new(tx).execute(&schema, request).await)
+//! }
+//! ```
+//!
+//! [`async-graphql`]: https://docs.rs/async-graphql
+//! [`Tx`]: crate::Tx
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use async_graphql::{BatchRequest, BatchResponse, ObjectType, Schema, SubscriptionType};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{Error, Marker, Tx};
+
+/// A handle to the request's transaction that can be shared between GraphQL resolvers.
+///
+/// See the [module documentation](self) for more information.
+pub struct SharedTx<DB: Marker, E = Error> {
+    shared: Arc<Shared<DB, E>>,
+}
+
+struct Shared<DB: Marker, E> {
+    tx: Mutex<Tx<DB, E>>,
+    rollback_only: AtomicBool,
+}
+
+impl<DB: Marker, E> SharedTx<DB, E> {
+    /// Share the given transaction.
+    pub fn new(tx: Tx<DB, E>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                tx: Mutex::new(tx),
+                rollback_only: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Wait for exclusive access to the transaction.
+    ///
+    /// The guard dereferences to [`Tx`], so `&mut *guard` can be used as an [`sqlx::Executor`].
+    /// Resolvers run concurrently, so the guard should be dropped as soon as possible to let other
+    /// resolvers use the transaction.
+    pub async fn lock(&self) -> MutexGuard<'_, Tx<DB, E>> {
+        self.shared.tx.lock().await
+    }
+
+    /// Roll back the transaction once the request has been executed, even if it succeeds.
+    pub fn set_rollback_only(&self) {
+        self.shared.rollback_only.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`set_rollback_only`](Self::set_rollback_only) has been called.
+    pub fn is_rollback_only(&self) -> bool {
+        self.shared.rollback_only.load(Ordering::Relaxed)
+    }
+
+    /// Execute a (batch) request against `schema`, with this handle as context data.
+    ///
+    /// The transaction is rolled back if the response contains any errors, or if
+    /// [`set_rollback_only`](Self::set_rollback_only) was called. Otherwise it's left for the
+    /// [`Layer`](crate::Layer) to resolve. A failure to roll back is reported to the
+    /// [`Observer`](crate::Observer) as [`Event::RollbackFailed`](crate::Event::RollbackFailed).
+    pub async fn execute<Query, Mutation, Subscription>(
+        self,
+        schema: &Schema<Query, Mutation, Subscription>,
+        request: impl Into<BatchRequest>,
+    ) -> BatchResponse
+    where
+        DB: Send + Sync,
+        E: Send + Sync + 'static,
+        Query: ObjectType + 'static,
+        Mutation: ObjectType + 'static,
+        Subscription: SubscriptionType + 'static,
+    {
+        let response = schema
+            .execute_batch(request.into().data(self.clone()))
+            .await;
+        if !response.is_ok() || self.is_rollback_only() {
+            self.lock().await.rollback_in_place().await;
+        }
+        response
+    }
+}
+
+impl<DB: Marker, E> Clone for SharedTx<DB, E> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<DB: Marker, E> fmt::Debug for SharedTx<DB, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedTx")
+            .field("rollback_only", &self.is_rollback_only())
+            .finish_non_exhaustive()
+    }
+}
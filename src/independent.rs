@@ -0,0 +1,220 @@
+//! An extractor for a transaction that's independent of the request.
+
+use std::{fmt, marker::PhantomData};
+
+use axum_core::{
+    extract::{FromRef, FromRequestParts},
+    response::IntoResponse,
+};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use http::request::Parts;
+use sqlx::{pool::PoolConnection, TransactionManager as _};
+
+use crate::{extension, Error, Event, Marker, State, TxOptions};
+
+/// An `axum` extractor for a database transaction that's independent of the request.
+///
+/// Unlike [`Tx`](crate::Tx), an `IndependentTx` is not bound to the request: it uses its own
+/// connection from the same pool, and it's always committed, regardless of the response status.
+/// This is useful for writes that must persist even if the request fails, such as audit logs.
+///
+/// The transaction is committed when the `IndependentTx` is dropped (i.e. when the handler
+/// returns), in a background task. Failures to commit on drop are reported to the configured
+/// [`Observer`](crate::Observer) as [`Event::IndependentCommitFailed`]. Use
+/// [`commit`](Self::commit) to commit explicitly and handle any error.
+///
+/// ```
+/// use axum_sqlx_tx::{IndependentTx, Tx};
+/// use sqlx::Sqlite;
+///
+/// async fn handler(mut tx: Tx<Sqlite>, mut audit: IndependentTx<Sqlite>) -> http::StatusCode {
+///     sqlx::query("INSERT INTO audit_log VALUES ('deleting everything')")
+///         .execute(&mut audit)
+///         .await
+///         .unwrap();
+///
+///     // The audit log entry is committed even though `tx` will be rolled back
+///     http::StatusCode::FORBIDDEN
+/// }
+/// ```
+///
+/// `IndependentTx` doesn't need the [`Layer`](crate::Layer), only [`State`]. It doesn't count
+/// towards the [transaction limit](crate::Config::max_transactions) (waiting for a permit while the
+/// request holds one could deadlock), and it's not tracked by [`State::drain`], though it can't be
+/// extracted once draining has started.
+pub struct IndependentTx<DB: Marker, E = Error> {
+    conn: Option<PoolConnection<DB::Driver>>,
+    state: State<DB>,
+    /// Whether to commit when dropped, rather than roll back.
+    commit_on_drop: bool,
+    _error: PhantomData<E>,
+}
+
+impl<DB: Marker, E> IndependentTx<DB, E> {
+    /// Commit the transaction.
+    pub async fn commit(mut self) -> Result<(), sqlx::Error> {
+        let conn = self
+            .conn
+            .as_mut()
+            .expect("BUG: IndependentTx without transaction");
+        let result = extension::commit::<DB>(conn).await;
+        self.conn = None;
+        result
+    }
+
+    /// Roll back the transaction.
+    pub async fn rollback(mut self) -> Result<(), sqlx::Error> {
+        self.commit_on_drop = false;
+        let conn = self
+            .conn
+            .as_mut()
+            .expect("BUG: IndependentTx without transaction");
+        let result = extension::rollback::<DB>(conn).await;
+        self.conn = None;
+        result
+    }
+}
+
+impl<DB: Marker, E> Drop for IndependentTx<DB, E> {
+    fn drop(&mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) if self.commit_on_drop => {
+                let state = self.state.clone();
+                runtime.spawn(async move {
+                    if let Err(error) = extension::commit::<DB>(&mut conn).await {
+                        state.observe(Event::IndependentCommitFailed { error: &error });
+                    }
+                });
+            }
+            // Without a runtime (or after `rollback` was cancelled) the transaction is rolled back
+            // when the connection is next used, as it would be for a dropped `sqlx::Transaction`
+            _ => <DB::Driver as sqlx::Database>::TransactionManager::start_rollback(&mut conn),
+        }
+    }
+}
+
+impl<DB: Marker, E> fmt::Debug for IndependentTx<DB, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndependentTx").finish_non_exhaustive()
+    }
+}
+
+impl<DB: Marker, E> AsRef<<DB::Driver as sqlx::Database>::Connection> for IndependentTx<DB, E> {
+    fn as_ref(&self) -> &<DB::Driver as sqlx::Database>::Connection {
+        self
+    }
+}
+
+impl<DB: Marker, E> AsMut<<DB::Driver as sqlx::Database>::Connection> for IndependentTx<DB, E> {
+    fn as_mut(&mut self) -> &mut <DB::Driver as sqlx::Database>::Connection {
+        self
+    }
+}
+
+impl<DB: Marker, E> std::ops::Deref for IndependentTx<DB, E> {
+    type Target = <DB::Driver as sqlx::Database>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+            .as_ref()
+            .expect("BUG: IndependentTx without transaction")
+    }
+}
+
+impl<DB: Marker, E> std::ops::DerefMut for IndependentTx<DB, E> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn
+            .as_mut()
+            .expect("BUG: IndependentTx without transaction")
+    }
+}
+
+impl<DB: Marker, S, E> FromRequestParts<S> for IndependentTx<DB, E>
+where
+    S: Sync,
+    E: From<Error> + IntoResponse + Send,
+    State<DB>: FromRef<S>,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::<DB>::from_ref(state);
+        if state.is_draining() {
+            return Err(Error::Draining.into());
+        }
+        let conn = state.transaction(TxOptions::default(), None).await?;
+
+        Ok(Self {
+            conn: Some(conn),
+            state,
+            commit_on_drop: true,
+            _error: PhantomData,
+        })
+    }
+}
+
+impl<'c, DB, E> sqlx::Executor<'c> for &'c mut IndependentTx<DB, E>
+where
+    DB: Marker,
+    for<'t> &'t mut <DB::Driver as sqlx::Database>::Connection:
+        sqlx::Executor<'t, Database = DB::Driver>,
+    E: std::fmt::Debug + Send,
+{
+    type Database = DB::Driver;
+
+    #[allow(clippy::type_complexity)]
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<
+        'e,
+        Result<
+            sqlx::Either<
+                <Self::Database as sqlx::Database>::QueryResult,
+                <Self::Database as sqlx::Database>::Row,
+            >,
+            sqlx::Error,
+        >,
+    >
+    where
+        'c: 'e,
+        Q: sqlx::Execute<'q, Self::Database> + 'q,
+    {
+        (&mut **self).fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxFuture<'e, Result<Option<<Self::Database as sqlx::Database>::Row>, sqlx::Error>>
+    where
+        'c: 'e,
+        Q: sqlx::Execute<'q, Self::Database> + 'q,
+    {
+        (&mut **self).fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [<Self::Database as sqlx::Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Self::Database as sqlx::Database>::Statement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut **self).prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<sqlx::Describe<Self::Database>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut **self).describe(sql)
+    }
+}
@@ -0,0 +1,72 @@
+use bytes::Bytes;
+
+/// Decides whether to commit based on the response body.
+///
+/// Some protocols (e.g. GraphQL and JSON-RPC) respond with HTTP 200 even when requests fail, and
+/// report failures in the response body. Set a body classifier with
+/// [`Config::body_classifier`](crate::Config::body_classifier) to inspect successful responses
+/// before committing. The classifier is only consulted for responses that would otherwise be
+/// committed.
+///
+/// `BodyClassifier` is implemented for closures taking the response head and body:
+///
+/// ```
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// let (state, layer) = Tx::config(pool)
+///     .body_classifier(64 * 1024, |_: &http::response::Parts, body: &bytes::Bytes| {
+///         !body.starts_with(b"error:")
+///     })
+///     .setup();
+/// # }
+/// ```
+pub trait BodyClassifier: Send + Sync + 'static {
+    /// Returns `true` if the transaction should be committed, or `false` to roll it back.
+    fn commit(&self, parts: &http::response::Parts, body: &Bytes) -> bool;
+}
+
+impl<F> BodyClassifier for F
+where
+    F: Fn(&http::response::Parts, &Bytes) -> bool + Send + Sync + 'static,
+{
+    fn commit(&self, parts: &http::response::Parts, body: &Bytes) -> bool {
+        self(parts, body)
+    }
+}
+
+/// A [`BodyClassifier`] for GraphQL responses.
+///
+/// The transaction is committed if the body is a JSON object without errors, i.e. its `errors`
+/// field is missing, `null` or an empty array. Anything else is rolled back.
+///
+/// ```
+/// use axum_sqlx_tx::GraphQlClassifier;
+///
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// let (state, layer) = Tx::config(pool)
+///     .body_classifier(1024 * 1024, GraphQlClassifier)
+///     .setup();
+/// # }
+/// ```
+#[cfg(feature = "graphql-classifier")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GraphQlClassifier;
+
+#[cfg(feature = "graphql-classifier")]
+impl BodyClassifier for GraphQlClassifier {
+    fn commit(&self, _parts: &http::response::Parts, body: &Bytes) -> bool {
+        let Ok(serde_json::Value::Object(response)) = serde_json::from_slice(body) else {
+            return false;
+        };
+        match response.get("errors") {
+            None | Some(serde_json::Value::Null) => true,
+            Some(serde_json::Value::Array(errors)) => errors.is_empty(),
+            Some(_) => false,
+        }
+    }
+}
@@ -1,13 +1,28 @@
 //! A [`tower_layer::Layer`] that enables the [`Tx`](crate::Tx) extractor.
 
-use std::marker::PhantomData;
+use std::{
+    any::Any,
+    future::Future,
+    marker::PhantomData,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
 
 use axum_core::response::IntoResponse;
 use bytes::Bytes;
 use futures_core::future::BoxFuture;
+use http::{header::TRAILER, HeaderValue};
 use http_body::Body;
+use tokio::sync::oneshot;
 
-use crate::{extension::Extension, Marker, State};
+use crate::{
+    body::{grpc_internal, grpc_ok, Buffered, Policy, Resolving, OUTCOME},
+    constraint::Violation,
+    extension::Extension,
+    stream::ResolveOnBodyEnd,
+    Constraint, Error, Event, Marker, OnPanic, Rollback, State,
+};
 
 /// A [`tower_layer::Layer`] that enables the [`Tx`] extractor.
 ///
@@ -16,7 +31,17 @@ use crate::{extension::Extension, Marker, State};
 /// [`sqlx::Pool`] and a transaction is started on it. The same transaction will be returned for
 /// subsequent uses of [`Tx`] on the same request. The inner service is then called as normal. Once
 /// the inner service responds, the transaction is committed or rolled back depending on the status
-/// code of the response.
+/// code of the response (see [`Config::resolve_on_body_end`](crate::Config::resolve_on_body_end) to
+/// resolve after the body has been sent instead).
+///
+/// If the request already has a transaction for the same database (i.e. the layer has been applied
+/// more than once, such as to both a router and a nested router), the outer layer's transaction is
+/// used, and only the outer layer resolves it. This requires both layers to have the same
+/// [`State`], otherwise the request fails with [`Error::ConflictingLayers`].
+///
+/// Rollbacks are awaited before the response is returned. If a rollback fails, the failure is
+/// reported to the configured [`Observer`](crate::Observer) as
+/// [`Event::RollbackFailed`](crate::Event::RollbackFailed).
 ///
 /// [`Tx`]: crate::Tx
 /// [request extensions]: https://docs.rs/http/latest/http/struct.Extensions.html
@@ -28,7 +53,7 @@ pub struct Layer<DB: Marker, E> {
 impl<DB: Marker, E> Layer<DB, E>
 where
     E: IntoResponse,
-    sqlx::Error: Into<E>,
+    Error: Into<E>,
 {
     pub(crate) fn new(state: State<DB>) -> Self {
         Self {
@@ -50,7 +75,7 @@ impl<DB: Marker, E> Clone for Layer<DB, E> {
 impl<DB: Marker, S, E> tower_layer::Layer<S> for Layer<DB, E>
 where
     E: IntoResponse,
-    sqlx::Error: Into<E>,
+    Error: Into<E>,
 {
     type Service = Service<DB, S, E>;
 
@@ -93,7 +118,7 @@ where
     >,
     S::Future: Send + 'static,
     E: IntoResponse,
-    sqlx::Error: Into<E>,
+    Error: Into<E>,
     ResBody: Body<Data = Bytes> + Send + 'static,
     ResBody::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
 {
@@ -109,25 +134,269 @@ where
     }
 
     fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
-        let ext = Extension::new(self.state.clone());
+        // If the layer is applied twice (e.g. on a router and a nested router), reuse the outer
+        // layer's transaction rather than shadowing it, and leave resolving it to the outer layer
+        if let Some(outer) = req.extensions().get::<Extension<DB>>() {
+            if !outer.state().is_same(&self.state) {
+                let res = Error::ConflictingLayers.into().into_response();
+                return Box::pin(async move { Ok(res) });
+            }
+            let res = self.inner.call(req);
+            return Box::pin(async move { Ok(res.await?.map(axum_core::body::Body::new)) });
+        }
+
+        let state = self.state.clone();
+        let ext = Extension::new(state.clone());
         req.extensions_mut().insert(ext.clone());
 
         let res = self.inner.call(req);
+        #[cfg(feature = "tower-sessions")]
+        let res = crate::session::scope(&ext, res);
+        let res = Guarded::new(res, &ext, &state);
 
         Box::pin(async move {
-            let res = res.await.unwrap(); // inner service is infallible
+            let res = match res.await {
+                Ok(res) => res.unwrap(), // inner service is infallible
+                Err(panic) => return Ok(respond_after_panic::<_, E>(&ext, &state, panic).await),
+            };
 
-            if !res.status().is_server_error() && !res.status().is_client_error() {
-                if let Err(error) = ext.resolve().await {
-                    return Ok(error.into().into_response());
+            let mut commit = !res.status().is_server_error() && !res.status().is_client_error();
+            if state.grpc() {
+                return Ok(grpc(res, ext, state, commit).await);
+            }
+            let res = match state.body_classifier() {
+                Some((limit, classifier)) if commit => {
+                    let (parts, body) = res.into_parts();
+                    // The body is still produced by the inner service, so a client disconnecting
+                    // while it's buffered is handled like a disconnect during the handler
+                    let collect = Guarded::new(Buffered::collect(body, limit), &ext, &state);
+                    let (body, complete) = match collect.await {
+                        Ok(collected) => collected,
+                        Err(panic) => {
+                            return Ok(respond_after_panic::<_, E>(&ext, &state, panic).await)
+                        }
+                    };
+                    commit = complete && classifier.commit(&parts, body.data());
+                    if !complete && body.data().len() > limit {
+                        state.observe(Event::BodyLimitExceeded { limit });
+                    }
+                    http::Response::from_parts(parts, axum_core::body::Body::new(body))
                 }
+                _ => res.map(axum_core::body::Body::new),
+            };
+            let on_body_end = res.extensions().get::<ResolveOnBodyEnd>().is_some();
+            if commit && (on_body_end || state.resolve_on_body_end()) {
+                // HTTP/1.1 servers only send trailers that are declared in advance
+                let mut res = res;
+                res.headers_mut()
+                    .append(TRAILER, HeaderValue::from_static(OUTCOME));
+                // Only the transaction is needed while the body is sent
+                ext.release_connection();
+                return Ok(res.map(|body| {
+                    axum_core::body::Body::new(Resolving::new(body, ext, state, Policy::BodyEnd))
+                }));
+            }
+
+            if let Err(error) = finish(ext, &state, commit).await {
+                let res = Constraint::of(&error)
+                    .and_then(|constraint| state.constraint_response(constraint));
+                return Ok(res.unwrap_or_else(|| Error::Commit { error }.into().into_response()));
             }
 
-            Ok(res.map(axum_core::body::Body::new))
+            // Apply the constraint classifier to errors returned by the inner service
+            let violation = res.extensions().get::<Violation>().copied();
+            Ok(violation
+                .and_then(|Violation(constraint)| state.constraint_response(constraint))
+                .unwrap_or(res))
         })
     }
 }
 
+/// Resolve the transaction based on the gRPC status of the response, see
+/// [`Config::grpc`](crate::Config::grpc).
+async fn grpc<DB: Marker, B>(
+    res: http::Response<B>,
+    ext: Extension<DB>,
+    state: State<DB>,
+    commit: bool,
+) -> http::Response<axum_core::body::Body>
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    // A "trailers-only" response has the status in its headers, otherwise wait for the trailers
+    let status = grpc_ok(res.headers());
+    if status.is_none() && commit {
+        ext.release_connection();
+        return res.map(|body| {
+            axum_core::body::Body::new(Resolving::new(body, ext, state, Policy::Grpc))
+        });
+    }
+
+    let commit = commit && status == Some(true);
+    let mut res = res.map(axum_core::body::Body::new);
+    if finish(ext, &state, commit).await.is_err() {
+        grpc_internal(res.headers_mut());
+    }
+    res
+}
+
+/// Roll back the transaction after the inner service panicked, and report it.
+pub(crate) async fn rollback_after_panic<DB: Marker>(
+    ext: &Extension<DB>,
+    state: &State<DB>,
+    panic: Box<dyn Any + Send>,
+) -> Box<dyn Any + Send> {
+    let rollback = ext.rollback().await;
+    state.observe(Event::Panicked {
+        message: panic_message(&*panic),
+        rollback: Rollback::of(&rollback),
+    });
+    panic
+}
+
+/// Roll back the transaction after the inner service panicked, then resume the panic or respond
+/// with [`Error::Panicked`] according to [`Config::on_panic`](crate::Config::on_panic).
+async fn respond_after_panic<DB: Marker, E>(
+    ext: &Extension<DB>,
+    state: &State<DB>,
+    panic: Box<dyn Any + Send>,
+) -> http::Response<axum_core::body::Body>
+where
+    E: IntoResponse,
+    Error: Into<E>,
+{
+    let panic = rollback_after_panic(ext, state, panic).await;
+    match state.on_panic() {
+        OnPanic::Resume => std::panic::resume_unwind(panic),
+        OnPanic::Respond => Error::Panicked.into().into_response(),
+    }
+}
+
+/// Release the request's resources once the inner service has responded, committing or rolling
+/// back the transaction.
+///
+/// Only commit errors are returned – rollback errors are reported to the observer, since the
+/// response is already an error.
+pub(crate) async fn finish<DB: Marker>(
+    ext: Extension<DB>,
+    state: &State<DB>,
+    commit: bool,
+) -> Result<(), sqlx::Error> {
+    // The response has been produced, so the plain connection (if any) is no longer needed
+    ext.release_connection();
+
+    let result = if state.cancellation_safe() {
+        resolve_detached(ext, state.clone(), commit).await
+    } else if commit {
+        ext.resolve().await
+    } else {
+        // A transaction that's still in use is rolled back when it's dropped
+        ext.rollback().await.unwrap_or(Ok(()))
+    };
+    match result {
+        Err(error) if !commit => {
+            state.observe(Event::RollbackFailed { error: &error });
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Drives the inner service's response future, catching panics and rolling back the transaction
+/// in the background if it's dropped before completing.
+///
+/// Rolling back on drop is only armed if
+/// [`Config::cancellation_safe`](crate::Config::cancellation_safe) is set.
+pub(crate) struct Guarded<DB: Marker, F> {
+    inner: Option<Pin<Box<F>>>,
+    armed: Option<(Extension<DB>, State<DB>)>,
+}
+
+impl<DB: Marker, F> Guarded<DB, F> {
+    pub(crate) fn new(inner: F, ext: &Extension<DB>, state: &State<DB>) -> Self {
+        Self {
+            inner: Some(Box::pin(inner)),
+            armed: state
+                .cancellation_safe()
+                .then(|| (ext.clone(), state.clone())),
+        }
+    }
+}
+
+impl<DB: Marker, F: Future> Future for Guarded<DB, F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = this
+            .inner
+            .as_mut()
+            .expect("BUG: polled Guarded after completion");
+        let poll = match std::panic::catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(panic) => Poll::Ready(Err(panic)),
+        };
+        let output = ready!(poll);
+
+        // Drop the inner future, so that any `Tx` it holds is released
+        this.inner = None;
+        this.armed = None;
+        Poll::Ready(output)
+    }
+}
+
+impl<DB: Marker, F> Drop for Guarded<DB, F> {
+    fn drop(&mut self) {
+        // Drop the inner future first, so that any `Tx` it holds is released
+        self.inner = None;
+
+        let Some((ext, state)) = self.armed.take() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let rollback = ext.rollback().await;
+                state.observe(Event::Cancelled {
+                    rollback: Rollback::of(&rollback),
+                });
+            });
+        }
+    }
+}
+
+/// Extract the message from a panic payload, if it has one.
+fn panic_message(panic: &(dyn Any + Send)) -> Option<&str> {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+}
+
+/// Resolve the transaction in a background task, so that resolution completes even if the caller
+/// is dropped.
+async fn resolve_detached<DB: Marker>(
+    ext: Extension<DB>,
+    state: State<DB>,
+    commit: bool,
+) -> Result<(), sqlx::Error> {
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let result = if commit {
+            ext.resolve().await
+        } else {
+            ext.rollback().await.unwrap_or(Ok(()))
+        };
+        if let Err(result) = tx.send(result) {
+            state.observe(Event::CancelledDuringResolution {
+                commit,
+                result: result.as_ref().map(|_| ()),
+            });
+        }
+    });
+    rx.await.unwrap_or(Err(sqlx::Error::WorkerCrashed))
+}
+
 #[cfg(test)]
 mod tests {
     use tokio::net::TcpListener;
@@ -0,0 +1,166 @@
+//! An extractor for a request-bound transaction that only begins when it's first used.
+
+use std::{fmt, panic::Location};
+
+use axum_core::{
+    extract::{FromRef, FromRequestParts},
+    response::IntoResponse,
+};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use http::request::Parts;
+
+use crate::{Error, Marker, State, Tx};
+
+/// An `axum` extractor for the request's transaction that only begins it when it's first used.
+///
+/// [`Tx`] acquires a connection and begins the transaction when it's extracted. Extracting a
+/// `LazyTx` is cheap: the connection is only acquired when the first query runs, so handlers that
+/// return early (e.g. after validation) don't tie up connections. `BEGIN` is still sent
+/// separately, before the first query. Otherwise it's the same transaction as [`Tx`], resolved by
+/// the [`Layer`](crate::Layer) in the same way.
+///
+/// `&mut LazyTx` implements [`sqlx::Executor`]:
+///
+/// ```
+/// use axum_sqlx_tx::LazyTx;
+/// use sqlx::Sqlite;
+///
+/// async fn handler(mut tx: LazyTx<Sqlite>, name: String) -> Result<(), axum_sqlx_tx::Error> {
+///     if name.is_empty() {
+///         // No connection was acquired for this request
+///         return Ok(());
+///     }
+///     sqlx::query("INSERT INTO users (name) VALUES ($1)")
+///         .bind(name)
+///         .execute(&mut tx)
+///         .await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Errors from beginning the transaction are returned from the query rather than the extractor.
+/// [`Error::PoolClosed`] becomes [`sqlx::Error::PoolClosed`], a failed `BEGIN` is returned as the
+/// underlying database error, and other errors (e.g. [`Error::Draining`] or [`Error::Saturated`])
+/// are boxed in [`sqlx::Error::Configuration`]. Converting the `sqlx::Error` back into an [`Error`]
+/// (e.g. with `?`) recovers the original error.
+///
+/// Unlike [`Tx`], `LazyTx` doesn't dereference to the connection, since there might not be one
+/// yet. Use [`ensure_begun`](Self::ensure_begun) to begin the transaction and get the [`Tx`].
+///
+/// The `E` generic parameter controls the error type returned when the extractor fails, as for
+/// [`Tx`].
+pub struct LazyTx<DB: Marker, E = Error> {
+    tx: Tx<DB, E>,
+}
+
+impl<DB: Marker, E> LazyTx<DB, E> {
+    /// Begin the transaction, if it hasn't begun already, and return it.
+    pub async fn ensure_begun(&mut self) -> Result<&mut Tx<DB, E>, Error> {
+        self.tx.ensure_begun().await?;
+        Ok(&mut self.tx)
+    }
+
+    /// Explicitly commit the transaction, if it has begun.
+    ///
+    /// See [`Tx::commit`].
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.commit().await
+    }
+
+    /// Commit the transaction, if it has begun, and begin a new one when it's next used.
+    ///
+    /// See [`Tx::commit_and_continue`].
+    pub async fn commit_and_continue(&mut self) -> Result<(), Error> {
+        self.tx.resolve_and_continue(true).await
+    }
+
+    /// Roll back the transaction, if it has begun, and begin a new one when it's next used.
+    ///
+    /// See [`Tx::rollback_and_continue`].
+    pub async fn rollback_and_continue(&mut self) -> Result<(), Error> {
+        self.tx.resolve_and_continue(false).await
+    }
+}
+
+impl<DB: Marker, E> fmt::Debug for LazyTx<DB, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyTx").finish_non_exhaustive()
+    }
+}
+
+impl<DB: Marker, S, E> FromRequestParts<S> for LazyTx<DB, E>
+where
+    S: Sync,
+    E: From<Error> + IntoResponse + Send,
+    State<DB>: FromRef<S>,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let tx = Tx::unbegun_from_extensions(&parts.extensions, Location::caller())?;
+        Ok(Self { tx })
+    }
+}
+
+impl<'c, DB, E> sqlx::Executor<'c> for &'c mut LazyTx<DB, E>
+where
+    DB: Marker,
+    for<'t> &'t mut <DB::Driver as sqlx::Database>::Connection:
+        sqlx::Executor<'t, Database = DB::Driver>,
+    E: std::fmt::Debug + Send,
+{
+    type Database = DB::Driver;
+
+    #[allow(clippy::type_complexity)]
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<
+        'e,
+        Result<
+            sqlx::Either<
+                <Self::Database as sqlx::Database>::QueryResult,
+                <Self::Database as sqlx::Database>::Row,
+            >,
+            sqlx::Error,
+        >,
+    >
+    where
+        'c: 'e,
+        Q: sqlx::Execute<'q, Self::Database> + 'q,
+    {
+        (&mut self.tx).fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxFuture<'e, Result<Option<<Self::Database as sqlx::Database>::Row>, sqlx::Error>>
+    where
+        'c: 'e,
+        Q: sqlx::Execute<'q, Self::Database> + 'q,
+    {
+        (&mut self.tx).fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [<Self::Database as sqlx::Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Self::Database as sqlx::Database>::Statement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut self.tx).prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<sqlx::Describe<Self::Database>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        (&mut self.tx).describe(sql)
+    }
+}
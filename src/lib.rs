@@ -40,6 +40,11 @@
 //! # }
 //! ```
 //!
+//! With the `axum` feature enabled,
+#![cfg_attr(feature = "axum", doc = "[`RouterExt::with_tx`]")]
+#![cfg_attr(not(feature = "axum"), doc = "`RouterExt::with_tx`")]
+//! does both in one step, so the layer can't be forgotten.
+//!
 //! You can then simply add [`Tx`] as an argument to your handlers:
 //!
 //! ```
@@ -52,21 +57,55 @@
 //!         .await
 //!         .unwrap();
 //!
-//!     // `Tx` also implements `Deref<Target = sqlx::Transaction>` and `DerefMut`
+//!     // `Tx` also implements `Deref` and `DerefMut` to the connection it's running on
 //!     use sqlx::Acquire;
 //!     let inner = tx.begin().await.unwrap();
 //!     /* ... */
 //! }
 //! ```
 //!
+//! Handlers that often return before running any queries (e.g. after validation) can use
+//! [`LazyTx`], which only acquires a connection and begins the transaction when it's first used.
+//!
+//! Writes that must persist regardless of the response (e.g. audit logs) can use
+//! [`IndependentTx`], which has its own transaction that's always committed. Statements that can't
+//! run in a transaction at all can use [`Conn`], a plain connection that's managed by the same
+//! [`Layer`]. With the `tower-sessions` feature enabled,
+#![cfg_attr(feature = "tower-sessions", doc = "[`session::Store`]")]
+#![cfg_attr(not(feature = "tower-sessions"), doc = "`session::Store`")]
+//! writes sessions through the request's transaction.
+//!
+//! Extractors that need to query the database (e.g. to load the current user) can implement
+//! [`FromTx`] and be extracted with [`Loaded`], which leaves the transaction available to the
+//! handler.
+//!
+//! Handlers that need their own transaction options, a timeout, or retries after serialization
+//! failures can use [`Transactional`] (or the `#[transactional]` attribute, with the `macros`
+//! feature).
+//!
 //! ## Error handling
 //!
 //! `axum` requires that errors can be turned into responses. The [`Error`] type converts into a
-//! HTTP 500 response with the error message as the response body. This may be suitable for
+//! HTTP 500 response with the error message as the response body, except for pool exhaustion
+//! which is reported as HTTP 503 (optionally with a `Retry-After` header). This may be suitable for
 //! development or internal services but it's generally not advisable to return internal error
 //! details to clients.
 //!
-//! See [`Error`] for how to customise error handling.
+//! See [`Error`] for how to customise error handling. With the `problem-json` feature enabled,
+#![cfg_attr(feature = "problem-json", doc = "[`problem::Problem`]")]
+#![cfg_attr(not(feature = "problem-json"), doc = "`problem::Problem`")]
+//! can be used to render errors as RFC 7807 `application/problem+json` responses instead.
+//!
+//! ## GraphQL
+//!
+//! With the `async-graphql` feature enabled,
+#![cfg_attr(feature = "async-graphql", doc = "[`graphql::SharedTx`]")]
+#![cfg_attr(not(feature = "async-graphql"), doc = "`graphql::SharedTx`")]
+//! shares the request's transaction between `async-graphql` resolvers. Without it,
+//! [`Config::body_classifier`] can be used to roll back GraphQL responses that contain errors (see
+#![cfg_attr(feature = "graphql-classifier", doc = "[`GraphQlClassifier`],")]
+#![cfg_attr(not(feature = "graphql-classifier"), doc = "`GraphQlClassifier`,")]
+//! behind the `graphql-classifier` feature).
 //!
 //! ## Multiple databases
 //!
@@ -98,19 +137,69 @@
 
 #![cfg_attr(doc, deny(warnings))]
 
+mod admission;
+mod body;
 mod config;
+mod conn;
+mod constraint;
 mod error;
 mod extension;
+pub mod generic;
+#[cfg(feature = "async-graphql")]
+pub mod graphql;
+mod independent;
+mod inspect;
 mod layer;
+mod lazy;
+mod load;
 mod marker;
+mod observer;
+#[cfg(feature = "problem-json")]
+pub mod problem;
+mod registry;
+#[cfg(feature = "axum")]
+mod router;
+#[cfg(feature = "tower-sessions")]
+pub mod session;
 mod state;
+mod stream;
+mod transactional;
 mod tx;
 
 pub use crate::{
+    admission::{Priority, Saturation},
     config::Config,
+    conn::Conn,
+    constraint::{Constraint, ConstraintClassifier},
     error::Error,
+    independent::IndependentTx,
+    inspect::BodyClassifier,
     layer::{Layer, Service},
+    lazy::LazyTx,
+    load::{FromTx, Loaded},
     marker::Marker,
+    observer::{Event, Observer, OnPanic, Rollback},
+    registry::Drained,
     state::State,
+    stream::TxStream,
+    transactional::{IsolationLevel, Transactional, TxOptions, TxState},
     tx::Tx,
 };
+
+#[cfg(feature = "axum")]
+pub use crate::router::RouterExt;
+
+#[cfg(feature = "graphql-classifier")]
+pub use crate::inspect::GraphQlClassifier;
+
+#[cfg(feature = "macros")]
+pub use axum_sqlx_tx_macros::transactional;
+
+/// Items used by the `#[transactional]` attribute. Not public API.
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use axum_core::response::Response;
+
+    pub fn assert_tx_state<T: crate::TxState<S>, S>() {}
+}
@@ -0,0 +1,134 @@
+//! Extractors that load data through the request's transaction.
+
+use std::{fmt, future::Future, marker::PhantomData, panic::Location};
+
+use axum_core::{
+    extract::{FromRef, FromRequestParts},
+    response::{IntoResponse, Response},
+};
+use http::request::Parts;
+
+use crate::{Error, Marker, State, Tx};
+
+/// A type that can be loaded using the request's transaction.
+///
+/// Extractors that query the database (e.g. to load the current user) can implement `FromTx`
+/// rather than [`FromRequestParts`], and be extracted with [`Loaded`]. This takes care of
+/// extracting the [`Tx`], and releases it once the value has been loaded, so that later extractors
+/// and the handler can still use the transaction.
+///
+/// ```
+/// use axum_sqlx_tx::FromTx;
+/// use http::{request::Parts, StatusCode};
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+/// type Loaded<T> = axum_sqlx_tx::Loaded<sqlx::Sqlite, T>;
+///
+/// struct CurrentUser {
+///     id: i64,
+///     name: String,
+/// }
+///
+/// impl FromTx<sqlx::Sqlite> for CurrentUser {
+///     type Rejection = StatusCode;
+///
+///     async fn from_tx(parts: &mut Parts, tx: &mut Tx) -> Result<Self, Self::Rejection> {
+///         let token = parts
+///             .headers
+///             .get("x-session-token")
+///             .and_then(|token| token.to_str().ok())
+///             .ok_or(StatusCode::UNAUTHORIZED)?;
+///         let (id, name) = sqlx::query_as("SELECT id, name FROM users WHERE token = ?")
+///             .bind(token)
+///             .fetch_optional(tx)
+///             .await
+///             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+///             .ok_or(StatusCode::UNAUTHORIZED)?;
+///         Ok(Self { id, name })
+///     }
+/// }
+///
+/// async fn handler(user: Loaded<CurrentUser>, mut tx: Tx) {
+///     // The handler can still use the transaction
+///     sqlx::query("UPDATE users SET last_seen = CURRENT_TIMESTAMP WHERE id = ?")
+///         .bind(user.id)
+///         .execute(&mut tx)
+///         .await
+///         .unwrap();
+/// }
+/// ```
+///
+/// The `E` generic parameter is the [`Tx`] error type.
+pub trait FromTx<DB: Marker, E = Error>: Sized {
+    /// The error returned if the value can't be loaded.
+    type Rejection: IntoResponse;
+
+    /// Load the value using the request's transaction.
+    fn from_tx(
+        parts: &mut Parts,
+        tx: &mut Tx<DB, E>,
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+/// An `axum` extractor for a type that implements [`FromTx`].
+///
+/// `Loaded<DB, T>` dereferences to the loaded `T`. If extracting the [`Tx`] or loading the value
+/// fails, the rejection is the error's response.
+pub struct Loaded<DB: Marker, T, E = Error> {
+    value: T,
+    _marker: PhantomData<fn() -> (DB, E)>,
+}
+
+impl<DB: Marker, T, E> Loaded<DB, T, E> {
+    /// The loaded value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<DB: Marker, T: fmt::Debug, E> fmt::Debug for Loaded<DB, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Loaded").field(&self.value).finish()
+    }
+}
+
+impl<DB: Marker, T, E> std::ops::Deref for Loaded<DB, T, E> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<DB: Marker, T, E> std::ops::DerefMut for Loaded<DB, T, E> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<DB: Marker, T, S, E> FromRequestParts<S> for Loaded<DB, T, E>
+where
+    T: FromTx<DB, E>,
+    S: Sync,
+    E: From<Error> + IntoResponse + Send,
+    State<DB>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let mut tx = Tx::<DB, E>::from_extensions_for(&parts.extensions, Location::caller())
+            .await
+            .map_err(|error| E::from(error).into_response())?;
+        let value = T::from_tx(parts, &mut tx)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        // Release the transaction, so that later extractors and the handler can use it
+        drop(tx);
+
+        Ok(Self {
+            value,
+            _marker: PhantomData,
+        })
+    }
+}
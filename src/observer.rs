@@ -0,0 +1,135 @@
+/// Receives [`Event`]s about transactions that were resolved out of the ordinary.
+///
+/// The [`Layer`](crate::Layer) middleware resolves transactions on behalf of handlers, so failures
+/// that happen after the response is decided (or that can't be reported in the response) would
+/// otherwise go unnoticed. Set an observer with [`Config::observer`](crate::Config::observer) to
+/// log or record them.
+///
+/// `Observer` is implemented for closures, so the simplest observer just logs events:
+///
+/// ```
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// let (state, layer) = Tx::config(pool)
+///     .observer(|event: axum_sqlx_tx::Event<'_>| eprintln!("transaction event: {event:?}"))
+///     .setup();
+/// # }
+/// ```
+pub trait Observer: Send + Sync + 'static {
+    /// Handle an event.
+    fn observe(&self, event: Event<'_>);
+}
+
+impl<F> Observer for F
+where
+    F: Fn(Event<'_>) + Send + Sync + 'static,
+{
+    fn observe(&self, event: Event<'_>) {
+        self(event)
+    }
+}
+
+/// Something notable that happened to a request's transaction.
+///
+/// See [`Observer`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// The response future was dropped before the inner service responded, and the transaction was
+    /// rolled back.
+    ///
+    /// This is only reported when [`Config::cancellation_safe`](crate::Config::cancellation_safe)
+    /// is enabled.
+    Cancelled {
+        /// The result of rolling back the transaction.
+        rollback: Rollback<'a>,
+    },
+
+    /// The inner service panicked, and the transaction was rolled back.
+    ///
+    /// What happens to the panic afterwards is controlled by
+    /// [`Config::on_panic`](crate::Config::on_panic).
+    Panicked {
+        /// The panic message, if it was a string.
+        message: Option<&'a str>,
+        /// The result of rolling back the transaction.
+        rollback: Rollback<'a>,
+    },
+
+    /// The inner service returned an error response, and rolling back the transaction failed.
+    ///
+    /// The connection may still be in the transaction, so it's closed rather than returned to the
+    /// pool.
+    RollbackFailed {
+        /// The error that occurred while rolling back.
+        error: &'a sqlx::Error,
+    },
+
+    /// An [`IndependentTx`](crate::IndependentTx) was dropped, and committing it failed.
+    IndependentCommitFailed {
+        /// The error that occurred while committing.
+        error: &'a sqlx::Error,
+    },
+
+    /// The response body was larger than the limit set with
+    /// [`Config::body_classifier`](crate::Config::body_classifier), so the transaction was rolled
+    /// back.
+    BodyLimitExceeded {
+        /// The configured limit, in bytes.
+        limit: usize,
+    },
+
+    /// The response future was dropped while the transaction was being resolved, and resolution
+    /// completed in the background.
+    ///
+    /// This is only reported when [`Config::cancellation_safe`](crate::Config::cancellation_safe)
+    /// is enabled.
+    CancelledDuringResolution {
+        /// Whether the transaction was being committed (rather than rolled back).
+        commit: bool,
+        /// The result of resolving the transaction.
+        result: Result<(), &'a sqlx::Error>,
+    },
+}
+
+/// The result of rolling back a transaction, for an [`Event`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Rollback<'a> {
+    /// The transaction was rolled back (or hadn't begun).
+    RolledBack,
+
+    /// Rolling back the transaction failed.
+    Failed(&'a sqlx::Error),
+
+    /// The transaction couldn't be rolled back because it's still in use, e.g. by a
+    /// [`Tx`](crate::Tx) that was moved into a spawned task. It's rolled back when that's dropped.
+    InUse,
+}
+
+impl<'a> Rollback<'a> {
+    /// The result of `Extension::rollback`.
+    pub(crate) fn of(result: &'a Option<Result<(), sqlx::Error>>) -> Self {
+        match result {
+            Some(Ok(())) => Self::RolledBack,
+            Some(Err(error)) => Self::Failed(error),
+            None => Self::InUse,
+        }
+    }
+}
+
+/// What to do when the inner service panics.
+///
+/// See [`Config::on_panic`](crate::Config::on_panic).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnPanic {
+    /// Resume unwinding after rolling back the transaction.
+    #[default]
+    Resume,
+
+    /// Respond with [`Error::Panicked`](crate::Error::Panicked) after rolling back the
+    /// transaction, converted into the [`Layer`](crate::Layer) error type.
+    Respond,
+}
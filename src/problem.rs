@@ -0,0 +1,150 @@
+//! [RFC 7807] `application/problem+json` error responses.
+//!
+//! [RFC 7807]: https://datatracker.ietf.org/doc/html/rfc7807
+
+use axum_core::response::IntoResponse;
+use http::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    HeaderValue,
+};
+
+use crate::{constraint::Violation, error::retry_after_header, transactional::Retryable, Error};
+
+/// An [`Error`] rendered as an [RFC 7807] `application/problem+json` response.
+///
+/// Each [`Error`] variant has a stable `type` URI (see [`Problem::type_uri`]), so clients can
+/// match on the kind of failure without parsing messages. By default, the response only contains
+/// the `type`, `title` and `status` members – driver messages and other internal details are
+/// hidden. Setting the `VERBOSE` parameter to `true` adds the error message as the `detail` member,
+/// which can be useful during development.
+///
+/// `Problem` is convertible from both [`Error`] and `sqlx::Error`, so it can be used as the
+/// [`Tx`](crate::Tx) error type, the [`Layer`](crate::Layer) error type, and the error type of
+/// handlers that run queries:
+///
+/// ```
+/// use axum_sqlx_tx::problem::Problem;
+///
+/// // Only reveal error details in debug builds
+/// type Error = Problem<{ cfg!(debug_assertions) }>;
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite, Error>;
+///
+/// async fn handler(mut tx: Tx) -> Result<(), Error> {
+///     sqlx::query("...").execute(&mut tx).await?;
+///     Ok(())
+/// }
+///
+/// # async fn foo() {
+/// let pool = sqlx::SqlitePool::connect("...").await.unwrap();
+/// let (state, layer) = Tx::config(pool).layer_error::<Error>().setup();
+/// # let app = axum::Router::new()
+/// #    .route("/", axum::routing::post(handler))
+/// #    .layer(layer)
+/// #    .with_state(state);
+/// # let listener: tokio::net::TcpListener = todo!();
+/// # axum::serve(listener, app);
+/// # }
+/// ```
+///
+/// [RFC 7807]: https://datatracker.ietf.org/doc/html/rfc7807
+#[derive(Debug)]
+pub struct Problem<const VERBOSE: bool = false>(Error);
+
+impl<const VERBOSE: bool> Problem<VERBOSE> {
+    /// The underlying error.
+    pub fn error(&self) -> &Error {
+        &self.0
+    }
+
+    /// Consume the problem, returning the underlying error.
+    pub fn into_inner(self) -> Error {
+        self.0
+    }
+
+    /// The `type` URI identifying the kind of problem.
+    pub fn type_uri(&self) -> &'static str {
+        match self.0 {
+            Error::MissingExtension => "urn:axum-sqlx-tx:error:missing-extension",
+            Error::OverlappingExtractors { .. } => "urn:axum-sqlx-tx:error:overlapping-extractors",
+            Error::ConflictingLayers => "urn:axum-sqlx-tx:error:conflicting-layers",
+            Error::AlreadyBegun => "urn:axum-sqlx-tx:error:already-begun",
+            Error::PoolTimedOut { .. } => "urn:axum-sqlx-tx:error:pool-timed-out",
+            Error::Saturated { .. } => "urn:axum-sqlx-tx:error:saturated",
+            Error::Draining => "urn:axum-sqlx-tx:error:draining",
+            Error::PoolClosed => "urn:axum-sqlx-tx:error:pool-closed",
+            Error::Acquire { .. } => "urn:axum-sqlx-tx:error:acquire",
+            Error::Begin { .. } => "urn:axum-sqlx-tx:error:begin",
+            Error::Commit { .. } => "urn:axum-sqlx-tx:error:commit",
+            Error::Rollback { .. } => "urn:axum-sqlx-tx:error:rollback",
+            Error::Panicked => "urn:axum-sqlx-tx:error:panicked",
+            Error::TimedOut => "urn:axum-sqlx-tx:error:timed-out",
+            Error::Query { .. } => "urn:axum-sqlx-tx:error:query",
+        }
+    }
+
+    /// A short, human-readable summary of the kind of problem.
+    pub fn title(&self) -> &'static str {
+        match self.0 {
+            Error::MissingExtension
+            | Error::OverlappingExtractors { .. }
+            | Error::ConflictingLayers
+            | Error::AlreadyBegun => "Misconfigured transaction",
+            Error::PoolTimedOut { .. }
+            | Error::Saturated { .. }
+            | Error::Draining
+            | Error::PoolClosed => "Database unavailable",
+            Error::Acquire { .. } => "Failed to acquire connection",
+            Error::Begin { .. } => "Failed to begin transaction",
+            Error::Commit { .. } => "Failed to commit transaction",
+            Error::Rollback { .. } => "Failed to roll back transaction",
+            Error::Panicked => "Request handler panicked",
+            Error::TimedOut => "Request handler timed out",
+            Error::Query { .. } => "Database query failed",
+        }
+    }
+}
+
+impl<const VERBOSE: bool> From<Error> for Problem<VERBOSE> {
+    fn from(error: Error) -> Self {
+        Self(error)
+    }
+}
+
+impl<const VERBOSE: bool> From<sqlx::Error> for Problem<VERBOSE> {
+    fn from(error: sqlx::Error) -> Self {
+        Self(error.into())
+    }
+}
+
+impl<const VERBOSE: bool> IntoResponse for Problem<VERBOSE> {
+    fn into_response(self) -> axum_core::response::Response {
+        let status = self.0.status();
+
+        let mut body = serde_json::json!({
+            "type": self.type_uri(),
+            "title": self.title(),
+            "status": status.as_u16(),
+        });
+        if VERBOSE {
+            body["detail"] = self.0.to_string().into();
+        }
+
+        let mut res = (status, body.to_string()).into_response();
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let Some(retry_after) = self.0.retry_after() {
+            res.headers_mut()
+                .insert(RETRY_AFTER, retry_after_header(retry_after));
+        }
+        if let Some(constraint) = self.0.constraint() {
+            res.extensions_mut().insert(Violation(constraint));
+        }
+        if self.0.is_retryable() {
+            res.extensions_mut().insert(Retryable);
+        }
+        res
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    panic::Location,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::{
+    extension::{LazyTransaction, WeakLock},
+    Marker,
+};
+
+/// The outcome of [`State::drain`](crate::State::drain).
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct Drained {
+    /// The number of transactions that were still open at the deadline and were rolled back.
+    pub rolled_back: usize,
+
+    /// The number of transactions that were still open at the deadline but couldn't be rolled back
+    /// because they were in use (e.g. by a handler that's still running).
+    pub in_use: usize,
+
+    /// Errors that occurred when rolling back transactions that were still open at the deadline.
+    pub errors: Vec<sqlx::Error>,
+}
+
+impl Drained {
+    /// Whether every transaction resolved before the deadline.
+    pub fn is_clean(&self) -> bool {
+        self.rolled_back == 0 && self.in_use == 0 && self.errors.is_empty()
+    }
+}
+
+type Slot<DB> = WeakLock<LazyTransaction<DB>>;
+
+/// Tracks open transactions so that they can be drained on shutdown.
+pub(crate) struct Registry<DB: Marker> {
+    draining: AtomicBool,
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, Slot<DB>>>,
+    closed: Notify,
+}
+
+/// An entry in the [`Registry`], removed on drop.
+pub(crate) struct Registration<DB: Marker> {
+    registry: Arc<Registry<DB>>,
+    id: u64,
+}
+
+impl<DB: Marker> Registry<DB> {
+    pub(crate) fn new() -> Self {
+        Self {
+            draining: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            open: Mutex::new(HashMap::new()),
+            closed: Notify::new(),
+        }
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.open.lock().len()
+    }
+
+    pub(crate) fn register(self: &Arc<Self>, slot: Slot<DB>) -> Registration<DB> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.open.lock().insert(id, slot);
+        Registration {
+            registry: self.clone(),
+            id,
+        }
+    }
+
+    pub(crate) async fn drain(&self, timeout: Duration) -> Drained {
+        self.draining.store(true, Ordering::Release);
+
+        let resolved = tokio::time::timeout(timeout, async {
+            loop {
+                let mut closed = pin!(self.closed.notified());
+                closed.as_mut().enable();
+
+                if self.open.lock().is_empty() {
+                    break;
+                }
+                closed.await;
+            }
+        })
+        .await;
+
+        let mut drained = Drained::default();
+        if resolved.is_ok() {
+            return drained;
+        }
+
+        let slots: Vec<_> = self
+            .open
+            .lock()
+            .values()
+            .filter_map(WeakLock::upgrade)
+            .collect();
+        for slot in slots {
+            let Ok(mut tx) = slot.lock(Location::caller()) else {
+                drained.in_use += 1;
+                continue;
+            };
+            match tx.abort().await {
+                Ok(()) => drained.rolled_back += 1,
+                Err(error) => drained.errors.push(error),
+            }
+        }
+        drained
+    }
+}
+
+impl<DB: Marker> fmt::Debug for Registry<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("draining", &self.is_draining())
+            .field("open", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<DB: Marker> Drop for Registration<DB> {
+    fn drop(&mut self) {
+        self.registry.open.lock().remove(&self.id);
+        self.registry.closed.notify_waiters();
+    }
+}
@@ -0,0 +1,51 @@
+//! An extension trait for installing the [`State`] and [`Layer`] on an `axum::Router`.
+
+use axum::Router;
+use axum_core::response::IntoResponse;
+
+use crate::{Config, Error, Layer, Marker, State};
+
+/// Extension methods for `axum::Router`, to set up the [`Tx`](crate::Tx) extractor in one step.
+///
+/// [`Tx::setup`](crate::Tx::setup) returns a [`State`] and a [`Layer`] that must both be applied to
+/// the router. Forgetting the layer is only noticed at runtime (as [`Error::MissingExtension`]),
+/// and it's easy to apply the pair to the wrong router when nesting. `with_tx` applies both:
+///
+/// ```
+/// use axum_sqlx_tx::RouterExt;
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// let app = axum::Router::new()
+///     .route("/", axum::routing::get(|tx: Tx| async move {}))
+///     .with_tx(Tx::config(pool));
+/// # let listener: tokio::net::TcpListener = todo!();
+/// # axum::serve(listener, app);
+/// # }
+/// ```
+///
+/// The router's state is the [`State<DB>`] for the configured database, so using a `Tx` for a
+/// different database is a compile error. Routers that need their own state type (e.g. for
+/// [multiple databases](crate::Marker)) should use [`Config::setup`] instead.
+pub trait RouterExt<DB: Marker>: Sized {
+    /// Create the [`State`] and [`Layer`] from `config`, and install both on the router.
+    fn with_tx<E, S>(self, config: Config<DB, E>) -> Router<S>
+    where
+        E: IntoResponse + Send + Sync + 'static,
+        Error: Into<E>,
+        S: Clone + Send + Sync + 'static;
+}
+
+impl<DB: Marker> RouterExt<DB> for Router<State<DB>> {
+    fn with_tx<E, S>(self, config: Config<DB, E>) -> Router<S>
+    where
+        E: IntoResponse + Send + Sync + 'static,
+        Error: Into<E>,
+        S: Clone + Send + Sync + 'static,
+    {
+        let (state, layer): (State<DB>, Layer<DB, E>) = config.setup();
+        self.layer(layer).with_state(state)
+    }
+}
@@ -0,0 +1,274 @@
+//! A [`tower-sessions`] store that writes sessions through the request's transaction.
+//!
+//! [`Store`] persists sessions to a database table. When a session is saved during a request whose
+//! transaction has begun, it's written using that transaction, so session changes are committed or
+//! rolled back together with the handler's changes. Otherwise (e.g. if the handler didn't use
+//! [`Tx`](crate::Tx)), a connection is taken from the pool in [`State`].
+//!
+//! For this to work, the `SessionManagerLayer` must be inside the [`Layer`](crate::Layer), i.e.
+//! added before it:
+//!
+//! ```
+//! use axum_sqlx_tx::session::Store;
+//! use tower_sessions::{ExpiredDeletion, SessionManagerLayer};
+//!
+//! type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+//!
+//! # async fn foo() {
+//! # let pool: sqlx::SqlitePool = todo!();
+//! let (state, layer) = Tx::setup(pool);
+//!
+//! let store = Store::new(state.clone());
+//! store.migrate().await.unwrap();
+//!
+//! // Delete expired sessions every minute
+//! tokio::spawn(
+//!     store
+//!         .clone()
+//!         .continuously_delete_expired(std::time::Duration::from_secs(60)),
+//! );
+//!
+//! let app = axum::Router::new()
+//!     // .route(...)s
+//! #   .route("/", axum::routing::get(|tx: Tx| async move {}))
+//!     .layer(SessionManagerLayer::new(store))
+//!     .layer(layer)
+//!     .with_state(state);
+//! # let listener: tokio::net::TcpListener = todo!();
+//! # axum::serve(listener, app);
+//! # }
+//! ```
+//!
+//! The queries are supported by SQLite and Postgres.
+//!
+//! [`tower-sessions`]: https://docs.rs/tower-sessions
+
+use std::{
+    fmt,
+    future::Future,
+    panic::Location,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use sqlx::pool::PoolConnection;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+use crate::{
+    extension::{Extension, Guard, LazyTransaction},
+    Marker, State,
+};
+
+tokio::task_local! {
+    /// The extensions of the requests being handled by the current task, see [`scope`].
+    static AMBIENT: http::Extensions;
+}
+
+/// Make the request's transaction available to [`Store`]s while `future` runs.
+pub(crate) fn scope<DB: Marker, F: Future>(
+    ext: &Extension<DB>,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    // Keep the extensions of any outer layers, e.g. for other databases
+    let mut ambient = AMBIENT.try_with(Clone::clone).unwrap_or_default();
+    ambient.insert(ext.clone());
+    AMBIENT.scope(ambient, future)
+}
+
+/// A [`SessionStore`] that writes sessions through the request's transaction.
+///
+/// See the [module documentation](self) for more information.
+pub struct Store<DB: Marker> {
+    state: State<DB>,
+    table: &'static str,
+}
+
+impl<DB: Marker> Store<DB> {
+    /// Create a store that uses the given [`State`].
+    ///
+    /// Sessions are stored in the `tower_sessions` table, see [`Store::migrate`].
+    pub fn new(state: State<DB>) -> Self {
+        Self {
+            state,
+            table: "tower_sessions",
+        }
+    }
+
+    /// Store sessions in the given table.
+    ///
+    /// The name is interpolated into queries as-is, so it must be a valid (and trusted) identifier.
+    pub fn table_name(mut self, table: &'static str) -> Self {
+        self.table = table;
+        self
+    }
+}
+
+impl<DB: Marker> Store<DB>
+where
+    for<'c> &'c mut <DB::Driver as sqlx::Database>::Connection:
+        sqlx::Executor<'c, Database = DB::Driver>,
+{
+    /// Create the sessions table, if it doesn't exist.
+    pub async fn migrate(&self) -> Result<(), sqlx::Error> {
+        let mut conn = self.state.pool().acquire().await?;
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                id TEXT PRIMARY KEY NOT NULL,
+                data TEXT NOT NULL,
+                expiry_date BIGINT NOT NULL
+            )",
+            self.table
+        );
+        sqlx::raw_sql(&query).execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Use the request's transaction if it has begun, or else a connection from the pool.
+    async fn connection(&self) -> Result<Connection<DB>, sqlx::Error> {
+        let ext = AMBIENT
+            .try_with(|ambient| ambient.get::<Extension<DB>>().cloned())
+            .ok()
+            .flatten();
+        if let Some(tx) = ext.and_then(|ext| ext.lock(Location::caller()).ok()) {
+            if tx.is_acquired() {
+                return Ok(Connection::Tx(tx));
+            }
+        }
+        Ok(Connection::Pool(self.state.pool().acquire().await?))
+    }
+}
+
+/// A connection to run session queries on, see [`Store::connection`].
+enum Connection<DB: Marker> {
+    Tx(Guard<LazyTransaction<DB>>),
+    Pool(PoolConnection<DB::Driver>),
+}
+
+impl<DB: Marker> Connection<DB> {
+    fn as_mut(&mut self) -> &mut <DB::Driver as sqlx::Database>::Connection {
+        match self {
+            Self::Tx(tx) => tx.as_mut(),
+            Self::Pool(conn) => conn,
+        }
+    }
+}
+
+#[async_trait]
+impl<DB: Marker> SessionStore for Store<DB>
+where
+    DB: Sync,
+    for<'c> &'c mut <DB::Driver as sqlx::Database>::Connection:
+        sqlx::Executor<'c, Database = DB::Driver>,
+    for<'q> <DB::Driver as sqlx::Database>::Arguments<'q>: sqlx::IntoArguments<'q, DB::Driver>,
+    for<'q> String:
+        sqlx::Type<DB::Driver> + sqlx::Encode<'q, DB::Driver> + sqlx::Decode<'q, DB::Driver>,
+    for<'q> i64: sqlx::Type<DB::Driver> + sqlx::Encode<'q, DB::Driver>,
+    usize: sqlx::ColumnIndex<<DB::Driver as sqlx::Database>::Row>,
+{
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let data = serde_json::to_string(record)
+            .map_err(|error| session_store::Error::Encode(error.to_string()))?;
+        let query = format!(
+            "INSERT INTO {} (id, data, expiry_date) VALUES ($1, $2, $3)
+            ON CONFLICT (id) DO UPDATE SET data = excluded.data, expiry_date = excluded.expiry_date",
+            self.table
+        );
+
+        let mut conn = self.connection().await.map_err(backend)?;
+        sqlx::query(&query)
+            .bind(record.id.to_string())
+            .bind(data)
+            .bind(record.expiry_date.unix_timestamp())
+            .execute(conn.as_mut())
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let query = format!(
+            "SELECT data FROM {} WHERE id = $1 AND expiry_date > $2",
+            self.table
+        );
+
+        let mut conn = self.connection().await.map_err(backend)?;
+        let data: Option<String> = sqlx::query_scalar(&query)
+            .bind(id.to_string())
+            .bind(now())
+            .fetch_optional(conn.as_mut())
+            .await
+            .map_err(backend)?;
+        data.map(|data| serde_json::from_str(&data))
+            .transpose()
+            .map_err(|error| session_store::Error::Decode(error.to_string()))
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        let query = format!("DELETE FROM {} WHERE id = $1", self.table);
+
+        let mut conn = self.connection().await.map_err(backend)?;
+        sqlx::query(&query)
+            .bind(id.to_string())
+            .execute(conn.as_mut())
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<DB: Marker> ExpiredDeletion for Store<DB>
+where
+    DB: Sync,
+    for<'c> &'c mut <DB::Driver as sqlx::Database>::Connection:
+        sqlx::Executor<'c, Database = DB::Driver>,
+    for<'q> <DB::Driver as sqlx::Database>::Arguments<'q>: sqlx::IntoArguments<'q, DB::Driver>,
+    for<'q> String:
+        sqlx::Type<DB::Driver> + sqlx::Encode<'q, DB::Driver> + sqlx::Decode<'q, DB::Driver>,
+    for<'q> i64: sqlx::Type<DB::Driver> + sqlx::Encode<'q, DB::Driver>,
+    usize: sqlx::ColumnIndex<<DB::Driver as sqlx::Database>::Row>,
+{
+    /// Delete expired sessions, using a connection from the pool.
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let query = format!("DELETE FROM {} WHERE expiry_date <= $1", self.table);
+
+        let mut conn = self.state.pool().acquire().await.map_err(backend)?;
+        sqlx::query(&query)
+            .bind(now())
+            .execute(&mut *conn)
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+impl<DB: Marker> Clone for Store<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            table: self.table,
+        }
+    }
+}
+
+impl<DB: Marker> fmt::Debug for Store<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Store")
+            .field("table", &self.table)
+            .finish_non_exhaustive()
+    }
+}
+
+fn backend(error: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(error.to_string())
+}
+
+/// The current time as a Unix timestamp, to compare with `expiry_date`.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
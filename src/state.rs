@@ -1,6 +1,20 @@
-use axum_core::extract::FromRef;
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::Marker;
+use axum_core::{extract::FromRef, response::Response};
+use sqlx::{pool::PoolConnection, TransactionManager as _};
+
+use crate::{
+    admission::{Admission, Permit},
+    extension::{LazyTransaction, WeakLock},
+    registry::{Drained, Registration, Registry},
+    BodyClassifier, Constraint, ConstraintClassifier, Error, Event, Marker, Observer, OnPanic,
+    Priority, TxOptions,
+};
 
 /// Application state that enables the [`Tx`] extractor.
 ///
@@ -15,17 +29,202 @@ use crate::Marker;
 #[derive(Debug)]
 pub struct State<DB: Marker> {
     pool: sqlx::Pool<DB::Driver>,
+    options: Arc<Options>,
+    registry: Arc<Registry<DB>>,
+}
+
+/// Options set via [`Config`](crate::Config).
+pub(crate) struct Options {
+    pub(crate) retry_after: Option<Duration>,
+    pub(crate) constraints: Option<ConstraintClassifier>,
+    pub(crate) admission: Option<Admission>,
+    pub(crate) observer: Option<Box<dyn Observer>>,
+    pub(crate) cancellation_safe: bool,
+    pub(crate) on_panic: OnPanic,
+    pub(crate) grpc: bool,
+    pub(crate) resolve_on_body_end: bool,
+    pub(crate) body_classifier: Option<(usize, Box<dyn BodyClassifier>)>,
 }
 
 impl<DB: Marker> State<DB> {
-    pub(crate) fn new(pool: sqlx::Pool<DB::Driver>) -> Self {
-        Self { pool }
+    pub(crate) fn new(pool: sqlx::Pool<DB::Driver>, options: Options) -> Self {
+        Self {
+            pool,
+            options: Arc::new(options),
+            registry: Arc::new(Registry::new()),
+        }
+    }
+
+    /// Whether `other` was created by the same [`Config::setup`](crate::Config::setup) call.
+    pub(crate) fn is_same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.options, &other.options)
+    }
+
+    /// The number of transactions that are currently open.
+    pub fn open_transactions(&self) -> usize {
+        self.registry.len()
     }
 
+    /// Stop beginning new transactions, and wait for open transactions to resolve.
+    ///
+    /// This is intended to be used during graceful shutdown, e.g. after the future passed to
+    /// `axum::serve(...).with_graceful_shutdown` resolves. Once `drain` is called, the
+    /// [`Tx`](crate::Tx) extractor will fail with [`Error::Draining`]. If any transactions are
+    /// still open after `timeout`, they are rolled back if possible, and the returned [`Drained`]
+    /// reports what was left. A transaction that's rolled back this way can no longer be committed,
+    /// so the [`Layer`](crate::Layer) reports it as a commit failure (e.g. an HTTP 500 response, or
+    /// a `commit-failed` outcome when [resolving at the end of the
+    /// body](crate::Config::resolve_on_body_end)).
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let pool: sqlx::SqlitePool = todo!();
+    /// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+    ///
+    /// let (state, layer) = Tx::setup(pool);
+    /// # let app = axum::Router::new()
+    /// #     .route("/", axum::routing::get(|_: Tx| async move {}))
+    /// #     .layer(layer)
+    /// #     .with_state(state.clone());
+    /// # let listener: tokio::net::TcpListener = todo!();
+    /// # let shutdown_signal = std::future::pending();
+    ///
+    /// axum::serve(listener, app)
+    ///     .with_graceful_shutdown(shutdown_signal)
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let drained = state.drain(std::time::Duration::from_secs(10)).await;
+    /// if !drained.is_clean() {
+    ///     eprintln!("transactions still open at shutdown: {drained:?}");
+    /// }
+    /// # }
+    /// ```
+    pub async fn drain(&self, timeout: Duration) -> Drained {
+        self.registry.drain(timeout).await
+    }
+
+    /// Whether [`drain`](Self::drain) has been called.
+    pub(crate) fn is_draining(&self) -> bool {
+        self.registry.is_draining()
+    }
+
+    /// Wait for permission to begin a transaction, if the number of transactions is limited.
+    pub(crate) async fn admit(&self, priority: Priority) -> Result<Option<Permit>, Error> {
+        if self.is_draining() {
+            return Err(Error::Draining);
+        }
+
+        let Some(admission) = &self.options.admission else {
+            return Ok(None);
+        };
+        let timeout = self.pool.options().get_acquire_timeout();
+        match admission.admit(priority, timeout).await {
+            Some(permit) => Ok(Some(permit)),
+            None => Err(Error::Saturated {
+                retry_after: self.options.retry_after,
+            }),
+        }
+    }
+
+    #[cfg(feature = "tower-sessions")]
+    pub(crate) fn pool(&self) -> &sqlx::Pool<DB::Driver> {
+        &self.pool
+    }
+
+    /// Acquire a connection and begin a transaction on it.
+    ///
+    /// If the request already waited for a [`Permit`] (since `admitted`), that time counts towards
+    /// the pool's acquire timeout, so that the total wait doesn't exceed it.
     pub(crate) async fn transaction(
         &self,
-    ) -> Result<sqlx::Transaction<'static, DB::Driver>, sqlx::Error> {
-        self.pool.begin().await
+        options: TxOptions,
+        admitted: Option<Instant>,
+    ) -> Result<PoolConnection<DB::Driver>, Error> {
+        let acquire = match admitted {
+            Some(admitted) => {
+                let timeout = self.pool.options().get_acquire_timeout();
+                let remaining = timeout.saturating_sub(admitted.elapsed());
+                tokio::time::timeout(remaining, self.pool.acquire())
+                    .await
+                    .unwrap_or(Err(sqlx::Error::PoolTimedOut))
+            }
+            None => self.pool.acquire().await,
+        };
+        let mut conn = acquire.map_err(|error| match error {
+            sqlx::Error::PoolTimedOut => Error::PoolTimedOut {
+                retry_after: self.options.retry_after,
+            },
+            sqlx::Error::PoolClosed => Error::PoolClosed,
+            error => Error::Begin { error },
+        })?;
+
+        let database = <DB::Driver as sqlx::Database>::NAME;
+        let statement = options.begin_statement(database).map(Cow::Owned);
+        <DB::Driver as sqlx::Database>::TransactionManager::begin(&mut conn, statement)
+            .await
+            .map_err(|error| Error::Begin { error })?;
+        Ok(conn)
+    }
+
+    pub(crate) async fn connection(&self) -> Result<PoolConnection<DB::Driver>, Error> {
+        if self.is_draining() {
+            return Err(Error::Draining);
+        }
+
+        self.pool.acquire().await.map_err(|error| match error {
+            sqlx::Error::PoolTimedOut => Error::PoolTimedOut {
+                retry_after: self.options.retry_after,
+            },
+            sqlx::Error::PoolClosed => Error::PoolClosed,
+            error => Error::Acquire { error },
+        })
+    }
+
+    /// Register an open transaction so that it can be drained.
+    pub(crate) fn register(&self, slot: WeakLock<LazyTransaction<DB>>) -> Registration<DB> {
+        self.registry.register(slot)
+    }
+
+    /// The configured response for a violation of `constraint`, if any.
+    pub(crate) fn constraint_response(&self, constraint: Constraint) -> Option<Response> {
+        self.options.constraints.as_ref()?.respond(constraint)
+    }
+
+    /// Whether transactions should be resolved in the background, see
+    /// [`Config::cancellation_safe`](crate::Config::cancellation_safe).
+    pub(crate) fn cancellation_safe(&self) -> bool {
+        self.options.cancellation_safe
+    }
+
+    /// What to do after rolling back when the inner service panics.
+    pub(crate) fn on_panic(&self) -> OnPanic {
+        self.options.on_panic
+    }
+
+    /// Whether to commit based on the gRPC status, see [`Config::grpc`](crate::Config::grpc).
+    pub(crate) fn grpc(&self) -> bool {
+        self.options.grpc
+    }
+
+    /// Whether to resolve once the response body ends, see
+    /// [`Config::resolve_on_body_end`](crate::Config::resolve_on_body_end).
+    pub(crate) fn resolve_on_body_end(&self) -> bool {
+        self.options.resolve_on_body_end
+    }
+
+    /// The body size limit and classifier, see
+    /// [`Config::body_classifier`](crate::Config::body_classifier).
+    pub(crate) fn body_classifier(&self) -> Option<(usize, &dyn BodyClassifier)> {
+        let (limit, classifier) = self.options.body_classifier.as_ref()?;
+        Some((*limit, &**classifier))
+    }
+
+    /// Report an event to the configured observer, if any.
+    pub(crate) fn observe(&self, event: Event<'_>) {
+        if let Some(observer) = &self.options.observer {
+            observer.observe(event);
+        }
     }
 }
 
@@ -33,10 +232,30 @@ impl<DB: Marker> Clone for State<DB> {
     fn clone(&self) -> Self {
         Self {
             pool: self.pool.clone(),
+            options: self.options.clone(),
+            registry: self.registry.clone(),
         }
     }
 }
 
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("retry_after", &self.retry_after)
+            .field("constraints", &self.constraints)
+            .field("admission", &self.admission)
+            .field("cancellation_safe", &self.cancellation_safe)
+            .field("on_panic", &self.on_panic)
+            .field("grpc", &self.grpc)
+            .field("resolve_on_body_end", &self.resolve_on_body_end)
+            .field(
+                "body_limit",
+                &self.body_classifier.as_ref().map(|(limit, _)| limit),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
 impl<DB: Marker> FromRef<State<DB>> for sqlx::Pool<DB::Driver> {
     fn from_ref(input: &State<DB>) -> Self {
         input.pool.clone()
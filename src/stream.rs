@@ -0,0 +1,141 @@
+//! A response that streams query results from the request's transaction.
+
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum_core::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_core::{future::BoxFuture, stream::BoxStream, Stream};
+use parking_lot::Mutex;
+
+use crate::{Marker, Tx};
+
+/// A response body that streams the results of a query on the request's transaction.
+///
+/// Usually the [`Layer`](crate::Layer) resolves the transaction before the response body is sent,
+/// and [`Tx`] can't outlive the handler. `TxStream` takes ownership of the `Tx`, and runs a query
+/// on it as the body is sent, encoding each row into the body. The transaction is resolved once the
+/// body ends, as with [`Config::resolve_on_body_end`](crate::Config::resolve_on_body_end) (which
+/// doesn't need to be enabled). If the query fails part way through, the body fails and the
+/// transaction is rolled back.
+///
+/// ```
+/// use axum::response::IntoResponse;
+/// use axum_sqlx_tx::TxStream;
+/// use http::header::CONTENT_TYPE;
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// async fn export(tx: Tx) -> impl IntoResponse {
+///     let body = TxStream::new(
+///         tx,
+///         |tx| sqlx::query_as("SELECT id, name FROM users").fetch(tx),
+///         |(id, name): (i32, String)| format!("{id},{name}\n").into(),
+///     );
+///     ([(CONTENT_TYPE, "text/csv")], body)
+/// }
+/// ```
+pub struct TxStream {
+    body: BoxStream<'static, Result<Bytes, sqlx::Error>>,
+}
+
+impl TxStream {
+    /// Stream the rows returned by `query`, encoded with `encode`.
+    pub fn new<DB, E, T, Q, F>(mut tx: Tx<DB, E>, query: Q, mut encode: F) -> Self
+    where
+        DB: Marker,
+        E: Send + 'static,
+        T: Send + 'static,
+        Q: for<'t> FnOnce(&'t mut Tx<DB, E>) -> BoxStream<'t, Result<T, sqlx::Error>>
+            + Send
+            + 'static,
+        F: FnMut(T) -> Bytes + Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(None));
+        let run = {
+            let slot = slot.clone();
+            async move {
+                let mut rows = query(&mut tx);
+                while let Some(row) = poll_fn(|cx| rows.as_mut().poll_next(cx)).await {
+                    *slot.lock() = Some(encode(row?));
+                    Yield(false).await;
+                }
+                Ok(())
+            }
+        };
+        Self {
+            body: Box::pin(Rows {
+                run: Some(Box::pin(run)),
+                slot,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Debug for TxStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxStream").finish_non_exhaustive()
+    }
+}
+
+impl IntoResponse for TxStream {
+    fn into_response(self) -> Response {
+        let mut res = axum_core::body::Body::from_stream(self.body).into_response();
+        res.extensions_mut().insert(ResolveOnBodyEnd);
+        res
+    }
+}
+
+/// The encoded rows of a running query.
+///
+/// The query's stream borrows the `Tx`, so both are kept in the `run` future. It leaves each
+/// encoded row in `slot` and then yields, so that the row can be returned from `poll_next`.
+struct Rows {
+    run: Option<BoxFuture<'static, Result<(), sqlx::Error>>>,
+    slot: Arc<Mutex<Option<Bytes>>>,
+}
+
+impl Stream for Rows {
+    type Item = Result<Bytes, sqlx::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(run) = &mut self.run else {
+            return Poll::Ready(None);
+        };
+        match run.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                self.run = None;
+                Poll::Ready(result.err().map(Err))
+            }
+            Poll::Pending => match self.slot.lock().take() {
+                Some(row) => Poll::Ready(Some(Ok(row))),
+                None => Poll::Pending,
+            },
+        }
+    }
+}
+
+/// A future that's pending the first time it's polled, without waking the task, since [`Rows`]
+/// returns the row that was just encoded instead.
+struct Yield(bool);
+
+impl Future for Yield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        Poll::Pending
+    }
+}
+
+/// Response extension telling the [`Layer`](crate::Layer) to resolve the transaction once the body
+/// ends.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResolveOnBodyEnd;
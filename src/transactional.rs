@@ -0,0 +1,319 @@
+//! Per-route transaction options, and handlers that run as (retryable) transactions.
+
+use std::{future::Future, marker::PhantomData, time::Duration};
+
+use axum_core::{
+    extract::{FromRef, FromRequestParts},
+    response::{IntoResponse, Response},
+};
+use http::request::Parts;
+
+use crate::{extension::Extension, Error, Event, Marker, State, Tx};
+
+/// The isolation level of a transaction, see [`TxOptions::isolation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IsolationLevel {
+    /// `READ UNCOMMITTED`.
+    ReadUncommitted,
+    /// `READ COMMITTED`.
+    ReadCommitted,
+    /// `REPEATABLE READ`.
+    RepeatableRead,
+    /// `SERIALIZABLE`.
+    Serializable,
+}
+
+impl IsolationLevel {
+    fn sql(self) -> &'static str {
+        match self {
+            Self::ReadUncommitted => "READ UNCOMMITTED",
+            Self::ReadCommitted => "READ COMMITTED",
+            Self::RepeatableRead => "REPEATABLE READ",
+            Self::Serializable => "SERIALIZABLE",
+        }
+    }
+}
+
+/// Options for beginning a request's transaction.
+///
+/// By default, transactions are begun with the driver's default `BEGIN` statement. Requests use
+/// other options if `TxOptions` are present in the [request extensions], which can be done
+/// per-route with `axum::Extension` (or with [`Transactional::options`]):
+///
+/// ```
+/// use axum::{routing::post, Extension};
+/// use axum_sqlx_tx::{IsolationLevel, TxOptions};
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// # async fn foo() {
+/// # let pool: sqlx::SqlitePool = todo!();
+/// let (state, layer) = Tx::setup(pool);
+///
+/// let serializable = TxOptions::new().isolation(IsolationLevel::Serializable);
+///
+/// let app = axum::Router::new()
+///     .route("/transfer", post(transfer).route_layer(Extension(serializable)))
+///     .layer(layer)
+///     .with_state(state);
+/// # let listener: tokio::net::TcpListener = todo!();
+/// # axum::serve(listener, app);
+/// # }
+/// # async fn transfer(_: Tx) {}
+/// ```
+///
+/// The transaction is begun with a statement for the pool's database:
+///
+/// - On Postgres (and other databases), `BEGIN ISOLATION LEVEL ... READ ONLY`.
+/// - On MySQL, `SET TRANSACTION ISOLATION LEVEL ...` followed by `START TRANSACTION READ ONLY`.
+/// - On SQLite, a plain `BEGIN`: SQLite transactions are always serializable, and read-only
+///   transactions aren't supported, so the options have no effect.
+///
+/// [request extensions]: https://docs.rs/http/latest/http/struct.Extensions.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TxOptions {
+    isolation: Option<IsolationLevel>,
+    read_only: bool,
+}
+
+impl TxOptions {
+    /// The default options.
+    pub const fn new() -> Self {
+        Self {
+            isolation: None,
+            read_only: false,
+        }
+    }
+
+    /// Set the isolation level of the transaction.
+    pub const fn isolation(mut self, isolation: IsolationLevel) -> Self {
+        self.isolation = Some(isolation);
+        self
+    }
+
+    /// Begin a read-only transaction.
+    pub const fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// The statement to begin the transaction with on the given database (see
+    /// [`sqlx::Database::NAME`]), if the default won't do.
+    pub(crate) fn begin_statement(&self, database: &str) -> Option<String> {
+        if *self == Self::default() || database == "SQLite" {
+            return None;
+        }
+        let mut statement = String::new();
+        match database {
+            "MySQL" => {
+                // MySQL can't set the isolation level in `START TRANSACTION`
+                if let Some(isolation) = self.isolation {
+                    statement.push_str("SET TRANSACTION ISOLATION LEVEL ");
+                    statement.push_str(isolation.sql());
+                    statement.push_str("; ");
+                }
+                statement.push_str("START TRANSACTION");
+            }
+            _ => {
+                statement.push_str("BEGIN");
+                if let Some(isolation) = self.isolation {
+                    statement.push_str(" ISOLATION LEVEL ");
+                    statement.push_str(isolation.sql());
+                }
+            }
+        }
+        if self.read_only {
+            statement.push_str(" READ ONLY");
+        }
+        Some(statement)
+    }
+}
+
+/// An `axum` extractor that runs a handler as a transaction, with its own options, timeout and
+/// retries.
+///
+/// `Transactional<Tx>` (for a [`Tx`] type) doesn't begin a transaction itself: instead,
+/// [`run`](Self::run) calls the given closure with a `Tx` that's begun with the configured
+/// [`TxOptions`]. The transaction is committed (or rolled back) according to the closure's response
+/// before `run` returns, rather than by the [`Layer`](crate::Layer), so that it can be retried if
+/// committing fails due to a serialization failure or deadlock (see [`Error::is_retryable`]). The
+/// closure is also retried if it responds with such an error (as [`Error`], or
+#[cfg_attr(feature = "problem-json", doc = "[`Problem`](crate::problem::Problem)")]
+#[cfg_attr(not(feature = "problem-json"), doc = "`Problem`")]
+/// with the `problem-json` feature).
+///
+/// ```
+/// use axum::{extract::Path, response::Response};
+/// use axum_sqlx_tx::{IsolationLevel, Transactional, TxOptions};
+/// use std::time::Duration;
+///
+/// type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+///
+/// async fn transfer(
+///     tx: Transactional<Tx>,
+///     Path((from, to, amount)): Path<(i64, i64, i64)>,
+/// ) -> Response {
+///     tx.options(TxOptions::new().isolation(IsolationLevel::Serializable))
+///         .retry(3)
+///         .timeout(Duration::from_secs(5))
+///         // The closure is called for each attempt
+///         .run(|mut tx: Tx| async move {
+///             sqlx::query("UPDATE accounts SET balance = balance - $1 WHERE id = $2")
+///                 .bind(amount)
+///                 .bind(from)
+///                 .execute(&mut tx)
+///                 .await?;
+///             sqlx::query("UPDATE accounts SET balance = balance + $1 WHERE id = $2")
+///                 .bind(amount)
+///                 .bind(to)
+///                 .execute(&mut tx)
+///                 .await?;
+///             Ok::<_, axum_sqlx_tx::Error>(())
+///         })
+///         .await
+/// }
+/// ```
+///
+/// With the `macros` feature, the `#[transactional]` attribute generates this from the handler's
+/// signature.
+pub struct Transactional<T> {
+    extensions: http::Extensions,
+    options: TxOptions,
+    retry: u32,
+    timeout: Option<Duration>,
+    _tx: PhantomData<fn() -> T>,
+}
+
+impl<DB: Marker, E> Transactional<Tx<DB, E>>
+where
+    E: From<Error> + IntoResponse,
+{
+    /// Set the options to begin the transaction with.
+    ///
+    /// Defaults to any [`TxOptions`] in the request extensions.
+    pub fn options(mut self, options: TxOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Retry the handler up to `retries` times if the transaction fails with a serialization
+    /// failure or deadlock.
+    ///
+    /// Defaults to `0`.
+    pub fn retry(mut self, retries: u32) -> Self {
+        self.retry = retries;
+        self
+    }
+
+    /// Roll back the transaction and respond with [`Error::TimedOut`] if an attempt takes longer
+    /// than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run `handler` with the transaction, and resolve it according to the response.
+    ///
+    /// `handler` is called again for each retry, with a new transaction. If the request's
+    /// transaction has already begun (e.g. because middleware used [`Tx`]), `handler` isn't called
+    /// and [`Error::AlreadyBegun`] is returned, since the transaction can't be begun with the
+    /// configured options, nor retried without repeating the earlier work.
+    pub async fn run<F, Fut, R>(mut self, mut handler: F) -> Response
+    where
+        F: FnMut(Tx<DB, E>) -> Fut,
+        Fut: Future<Output = R>,
+        R: IntoResponse,
+    {
+        let Some(ext) = self.extensions.get::<Extension<DB>>().cloned() else {
+            return E::from(Error::MissingExtension).into_response();
+        };
+        if ext.is_acquired() {
+            return E::from(Error::AlreadyBegun).into_response();
+        }
+        self.extensions.insert(self.options);
+
+        let mut attempt = 0;
+        loop {
+            let tx = match Tx::<DB, E>::from_extensions(&self.extensions).await {
+                Ok(tx) => tx,
+                Err(error) => return E::from(error).into_response(),
+            };
+
+            let res = match self.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, handler(tx)).await {
+                    Ok(res) => res.into_response(),
+                    Err(_) => {
+                        resolve(&ext, false).await.ok();
+                        return E::from(Error::TimedOut).into_response();
+                    }
+                },
+                None => handler(tx).await.into_response(),
+            };
+
+            let commit = !res.status().is_server_error() && !res.status().is_client_error();
+            let retry = attempt < self.retry;
+            match resolve(&ext, commit).await {
+                Ok(()) if !retry || res.extensions().get::<Retryable>().is_none() => return res,
+                Ok(()) => {}
+                Err(error) if retry && is_retryable(&error) => {}
+                Err(error) => return E::from(Error::Commit { error }).into_response(),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// Commit or roll back the request's transaction, leaving nothing for the [`Layer`](crate::Layer)
+/// to resolve.
+async fn resolve<DB: Marker>(ext: &Extension<DB>, commit: bool) -> Result<(), sqlx::Error> {
+    match ext.resolve_and_continue(commit).await {
+        Err(error) if !commit => {
+            ext.state().observe(Event::RollbackFailed { error: &error });
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+impl<DB: Marker, S, E> FromRequestParts<S> for Transactional<Tx<DB, E>>
+where
+    S: Sync,
+    Tx<DB, E>: TxState<S>,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            extensions: parts.extensions.clone(),
+            options: parts.extensions.get().copied().unwrap_or_default(),
+            retry: 0,
+            timeout: None,
+            _tx: PhantomData,
+        })
+    }
+}
+
+/// Asserts that a [`Tx`] type can be extracted with router state `S`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be extracted with router state `{S}`",
+    label = "the router state doesn't provide `axum_sqlx_tx::State` for this `Tx`",
+    note = "the `State<DB>` for the `Tx<DB>` marker type must be the router state, or be reachable from it with `FromRef`"
+)]
+pub trait TxState<S> {}
+
+impl<DB: Marker, E, S> TxState<S> for Tx<DB, E> where State<DB>: FromRef<S> {}
+
+/// Response extension marking responses for errors that could succeed if retried, see
+/// [`Error::is_retryable`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Retryable;
+
+/// Whether `error` is a serialization failure or deadlock.
+pub(crate) fn is_retryable(error: &sqlx::Error) -> bool {
+    let Some(code) = error.as_database_error().and_then(|error| error.code()) else {
+        return false;
+    };
+    // SQLSTATE `serialization_failure` and `deadlock_detected`, and SQLite's `SQLITE_BUSY` and
+    // `SQLITE_BUSY_SNAPSHOT`
+    matches!(&*code, "40001" | "40P01" | "5" | "517")
+}
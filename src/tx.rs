@@ -1,18 +1,24 @@
 //! A request extension that enables the [`Tx`](crate::Tx) extractor.
 
-use std::{fmt, marker::PhantomData};
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    panic::Location,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
 
 use axum_core::{
     extract::{FromRef, FromRequestParts},
     response::IntoResponse,
 };
-use futures_core::{future::BoxFuture, stream::BoxStream};
+use futures_core::{future::BoxFuture, stream::BoxStream, Stream};
 use http::request::Parts;
-use parking_lot::{lock_api::ArcMutexGuard, RawMutex};
 
 use crate::{
-    extension::{Extension, LazyTransaction},
-    Config, Error, Marker, State,
+    extension::{Extension, Guard, LazyTransaction},
+    Config, Error, Marker, Priority, State, TxOptions,
 };
 
 /// An `axum` extractor for a database transaction.
@@ -31,8 +37,8 @@ use crate::{
 /// }
 /// ```
 ///
-/// It also implements `Deref<Target = `[`sqlx::Transaction`]`>` and `DerefMut`, so you can call
-/// methods from `Transaction` and its traits:
+/// It also implements `Deref` and `DerefMut` to the underlying database connection (e.g.
+/// `SqliteConnection`), so you can call methods from [`sqlx::Connection`] and its traits:
 ///
 /// ```
 /// use axum_sqlx_tx::Tx;
@@ -74,7 +80,9 @@ use crate::{
 /// }
 /// ```
 pub struct Tx<DB: Marker, E = Error> {
-    tx: ArcMutexGuard<RawMutex, LazyTransaction<DB>>,
+    tx: Guard<LazyTransaction<DB>>,
+    priority: Priority,
+    options: TxOptions,
     _error: PhantomData<E>,
 }
 
@@ -113,6 +121,59 @@ impl<DB: Marker, E> Tx<DB, E> {
         Config::new(pool)
     }
 
+    /// Obtain the transaction from request extensions.
+    ///
+    /// This is what the extractor does, and can be used to obtain the transaction in services that
+    /// don't use `axum` extractors, e.g. with [`generic::Layer`](crate::generic::Layer).
+    ///
+    /// The caller's location is recorded as the holder of the transaction in debug builds, and
+    /// reported by [`Error::OverlappingExtractors`] if it's extracted again while held.
+    #[track_caller]
+    pub fn from_extensions(
+        extensions: &http::Extensions,
+    ) -> impl Future<Output = Result<Self, Error>> + use<'_, DB, E> {
+        Self::from_extensions_for(extensions, Location::caller())
+    }
+
+    /// Obtain the transaction on behalf of `holder`, where the extractor that will hold it was
+    /// obtained (see [`Error::OverlappingExtractors`]).
+    pub(crate) async fn from_extensions_for(
+        extensions: &http::Extensions,
+        holder: &'static Location<'static>,
+    ) -> Result<Self, Error> {
+        let ext: &Extension<DB> = extensions.get().ok_or(Error::MissingExtension)?;
+
+        let priority = extensions.get().copied().unwrap_or_default();
+        let options = extensions.get().copied().unwrap_or_default();
+        let tx = ext.acquire(priority, options, holder).await?;
+
+        Ok(Self {
+            tx,
+            priority,
+            options,
+            _error: PhantomData,
+        })
+    }
+
+    /// Obtain the transaction without beginning it, for [`LazyTx`](crate::LazyTx).
+    pub(crate) fn unbegun_from_extensions(
+        extensions: &http::Extensions,
+        holder: &'static Location<'static>,
+    ) -> Result<Self, Error> {
+        let ext: &Extension<DB> = extensions.get().ok_or(Error::MissingExtension)?;
+
+        let priority = extensions.get().copied().unwrap_or_default();
+        let options = extensions.get().copied().unwrap_or_default();
+        let tx = ext.lock(holder)?;
+
+        Ok(Self {
+            tx,
+            priority,
+            options,
+            _error: PhantomData,
+        })
+    }
+
     /// Explicitly commit the transaction.
     ///
     /// By default, the transaction will be committed when a successful response is returned
@@ -120,10 +181,104 @@ impl<DB: Marker, E> Tx<DB, E> {
     /// `3XX` response). This method allows the transaction to be committed explicitly.
     ///
     /// **Note:** trying to use the `Tx` extractor again after calling `commit` will currently
-    /// generate [`Error::OverlappingExtractors`] errors. This may change in future.
+    /// generate [`Error::OverlappingExtractors`] errors. Use
+    /// [`commit_and_continue`](Self::commit_and_continue) to keep using the `Tx` afterwards.
     pub async fn commit(mut self) -> Result<(), sqlx::Error> {
         self.tx.commit().await
     }
+
+    /// Commit the transaction and start a new one.
+    ///
+    /// This allows a single request to make its changes in several transactions, e.g. to import
+    /// data in chunks:
+    ///
+    /// ```
+    /// use axum_sqlx_tx::Tx;
+    /// use sqlx::Sqlite;
+    ///
+    /// async fn handler(mut tx: Tx<Sqlite>) -> Result<(), axum_sqlx_tx::Error> {
+    ///     for chunk in ["a", "b", "c"] {
+    ///         sqlx::query("INSERT INTO chunks VALUES ($1)")
+    ///             .bind(chunk)
+    ///             .execute(&mut tx)
+    ///             .await?;
+    ///         tx.commit_and_continue().await?;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// The connection (and [transaction limit](Config::max_transactions) permit, if any) is
+    /// released before the new transaction is started, and the new transaction is resolved by the
+    /// [`Layer`](crate::Layer) as usual.
+    ///
+    /// If committing fails, [`Error::Commit`] is returned without starting a new transaction. The
+    /// failed transaction is discarded, so the next query made with the `Tx` begins a new one (as
+    /// it would for a [`LazyTx`](crate::LazyTx)), while dereferencing it to its connection panics
+    /// until [`ensure_begun`](Self::ensure_begun) is called. The same applies if starting the new
+    /// transaction fails.
+    pub async fn commit_and_continue(&mut self) -> Result<(), Error> {
+        self.resolve_and_continue(true).await?;
+        self.ensure_begun().await
+    }
+
+    /// Roll back the transaction and start a new one.
+    ///
+    /// See [`commit_and_continue`](Self::commit_and_continue), which this mirrors. If rolling back
+    /// fails, [`Error::Rollback`] is returned.
+    pub async fn rollback_and_continue(&mut self) -> Result<(), Error> {
+        self.resolve_and_continue(false).await?;
+        self.ensure_begun().await
+    }
+
+    /// Begin the transaction, if it hasn't begun already.
+    ///
+    /// This is only needed after [`commit_and_continue`](Self::commit_and_continue) or
+    /// [`rollback_and_continue`](Self::rollback_and_continue) failed, to begin the transaction
+    /// before using `Tx` as anything other than an [`sqlx::Executor`] (e.g. to `Deref` it to its
+    /// connection).
+    pub async fn ensure_begun(&mut self) -> Result<(), Error> {
+        if self.tx.is_acquired() {
+            return Ok(());
+        }
+        let slot = Guard::downgrade(&self.tx);
+        self.tx.acquire(self.priority, self.options, slot).await
+    }
+
+    /// Roll back the transaction, leaving nothing for the [`Layer`](crate::Layer) to resolve.
+    ///
+    /// Rollback failures are reported to the observer, as they would be by the `Layer`.
+    #[cfg(feature = "async-graphql")]
+    pub(crate) async fn rollback_in_place(&mut self) {
+        let state = self.tx.state().cloned();
+        if let (Err(error), Some(state)) = (self.tx.rollback().await, state) {
+            state.observe(crate::Event::RollbackFailed { error: &error });
+        }
+    }
+
+    /// Commit or roll back the transaction, leaving the next one to begin when it's used.
+    pub(crate) async fn resolve_and_continue(&mut self, commit: bool) -> Result<(), Error> {
+        self.tx.resolve_and_continue(commit).await.map_err(|error| {
+            if commit {
+                Error::Commit { error }
+            } else {
+                Error::Rollback { error }
+            }
+        })
+    }
+
+    /// Begin the transaction for an [`sqlx::Executor`] method, which must return an `sqlx::Error`.
+    ///
+    /// Errors without an `sqlx::Error` equivalent are boxed in `sqlx::Error::Configuration`, and
+    /// unwrapped again by `From<sqlx::Error> for Error`, so `?` in a handler keeps the original.
+    async fn ensure_begun_for_query(&mut self) -> Result<(), sqlx::Error> {
+        self.ensure_begun().await.map_err(|error| match error {
+            Error::PoolTimedOut { retry_after: None } => sqlx::Error::PoolTimedOut,
+            Error::PoolClosed => sqlx::Error::PoolClosed,
+            Error::Begin { error } => error,
+            error => sqlx::Error::Configuration(Box::new(error)),
+        })
+    }
 }
 
 impl<DB: Marker, E> fmt::Debug for Tx<DB, E> {
@@ -132,20 +287,20 @@ impl<DB: Marker, E> fmt::Debug for Tx<DB, E> {
     }
 }
 
-impl<DB: Marker, E> AsRef<sqlx::Transaction<'static, DB::Driver>> for Tx<DB, E> {
-    fn as_ref(&self) -> &sqlx::Transaction<'static, DB::Driver> {
+impl<DB: Marker, E> AsRef<<DB::Driver as sqlx::Database>::Connection> for Tx<DB, E> {
+    fn as_ref(&self) -> &<DB::Driver as sqlx::Database>::Connection {
         self.tx.as_ref()
     }
 }
 
-impl<DB: Marker, E> AsMut<sqlx::Transaction<'static, DB::Driver>> for Tx<DB, E> {
-    fn as_mut(&mut self) -> &mut sqlx::Transaction<'static, DB::Driver> {
+impl<DB: Marker, E> AsMut<<DB::Driver as sqlx::Database>::Connection> for Tx<DB, E> {
+    fn as_mut(&mut self) -> &mut <DB::Driver as sqlx::Database>::Connection {
         self.tx.as_mut()
     }
 }
 
 impl<DB: Marker, E> std::ops::Deref for Tx<DB, E> {
-    type Target = sqlx::Transaction<'static, DB::Driver>;
+    type Target = <DB::Driver as sqlx::Database>::Connection;
 
     fn deref(&self) -> &Self::Target {
         self.tx.as_ref()
@@ -167,14 +322,7 @@ where
     type Rejection = E;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let ext: &Extension<DB> = parts.extensions.get().ok_or(Error::MissingExtension)?;
-
-        let tx = ext.acquire().await?;
-
-        Ok(Self {
-            tx,
-            _error: PhantomData,
-        })
+        Ok(Self::from_extensions(&parts.extensions).await?)
     }
 }
 
@@ -205,7 +353,13 @@ where
         'c: 'e,
         Q: sqlx::Execute<'q, Self::Database> + 'q,
     {
-        (&mut ***self).fetch_many(query)
+        if self.tx.is_acquired() {
+            return (&mut **self).fetch_many(query);
+        }
+        Box::pin(Lazy::Begin(Box::pin(async move {
+            self.ensure_begun_for_query().await?;
+            Ok((&mut **self).fetch_many(query))
+        })))
     }
 
     fn fetch_optional<'e, 'q: 'e, Q>(
@@ -216,7 +370,13 @@ where
         'c: 'e,
         Q: sqlx::Execute<'q, Self::Database> + 'q,
     {
-        (&mut ***self).fetch_optional(query)
+        if self.tx.is_acquired() {
+            return (&mut **self).fetch_optional(query);
+        }
+        Box::pin(async move {
+            self.ensure_begun_for_query().await?;
+            (&mut **self).fetch_optional(query).await
+        })
     }
 
     fn prepare_with<'e, 'q: 'e>(
@@ -227,7 +387,13 @@ where
     where
         'c: 'e,
     {
-        (&mut ***self).prepare_with(sql, parameters)
+        if self.tx.is_acquired() {
+            return (&mut **self).prepare_with(sql, parameters);
+        }
+        Box::pin(async move {
+            self.ensure_begun_for_query().await?;
+            (&mut **self).prepare_with(sql, parameters).await
+        })
     }
 
     fn describe<'e, 'q: 'e>(
@@ -237,6 +403,39 @@ where
     where
         'c: 'e,
     {
-        (&mut ***self).describe(sql)
+        if self.tx.is_acquired() {
+            return (&mut **self).describe(sql);
+        }
+        Box::pin(async move {
+            self.ensure_begun_for_query().await?;
+            (&mut **self).describe(sql).await
+        })
+    }
+}
+
+/// A stream that begins the transaction before running the query, see [`LazyTx`](crate::LazyTx).
+enum Lazy<'e, T> {
+    Begin(BoxFuture<'e, Result<BoxStream<'e, Result<T, sqlx::Error>>, sqlx::Error>>),
+    Query(BoxStream<'e, Result<T, sqlx::Error>>),
+    Done,
+}
+
+impl<T> Stream for Lazy<'_, T> {
+    type Item = Result<T, sqlx::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match &mut *self {
+                Self::Begin(begin) => match ready!(begin.as_mut().poll(cx)) {
+                    Ok(query) => *self = Self::Query(query),
+                    Err(error) => {
+                        *self = Self::Done;
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                },
+                Self::Query(query) => return query.as_mut().poll_next(cx),
+                Self::Done => return Poll::Ready(None),
+            }
+        }
     }
 }
@@ -0,0 +1,113 @@
+#![cfg(feature = "async-graphql")]
+
+use async_graphql::{Context, EmptySubscription, Object, Schema};
+use axum_sqlx_tx::graphql::SharedTx;
+use tower::ServiceExt;
+
+type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+
+type AppSchema = Schema<Query, Mutation, EmptySubscription>;
+
+struct Query;
+
+#[Object]
+impl Query {
+    async fn users(&self, ctx: &Context<'_>) -> async_graphql::Result<i64> {
+        let mut tx = ctx.data::<SharedTx<sqlx::Sqlite>>()?.lock().await;
+        let (count,) = sqlx::query_as("SELECT COUNT(*) FROM users")
+            .fetch_one(&mut *tx)
+            .await?;
+        Ok(count)
+    }
+}
+
+struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn create_user(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<i32> {
+        let mut tx = ctx.data::<SharedTx<sqlx::Sqlite>>()?.lock().await;
+        sqlx::query("INSERT INTO users VALUES (?, 'graphql')")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        Ok(id)
+    }
+
+    async fn discard(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        ctx.data::<SharedTx<sqlx::Sqlite>>()?.set_rollback_only();
+        Ok(true)
+    }
+}
+
+#[tokio::test]
+async fn shared_tx() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY, name TEXT);")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (state, layer) = Tx::setup(pool.clone());
+    let schema = AppSchema::new(Query, Mutation, EmptySubscription);
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::post(
+                |axum::Extension(schema): axum::Extension<AppSchema>,
+                 tx: Tx,
+                 axum::Json(request): axum::Json<async_graphql::Request>| async move {
+                    axum::Json(SharedTx::new(tx).execute(&schema, request).await)
+                },
+            ),
+        )
+        .layer(layer)
+        .layer(axum::Extension(schema))
+        .with_state(state.clone());
+
+    let request = |query: &str| {
+        let app = app.clone();
+        let body = serde_json::json!({ "query": query }).to_string();
+        async move {
+            let response = app
+                .oneshot(
+                    http::Request::builder()
+                        .method(http::Method::POST)
+                        .uri("/")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(axum::body::Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    // Both mutations use the request's transaction, which is committed
+    let response = request("mutation { a: createUser(id: 1) b: createUser(id: 2) }").await;
+    assert_eq!(response["data"], serde_json::json!({ "a": 1, "b": 2 }));
+    let response = request("{ users }").await;
+    assert_eq!(response["data"]["users"], 2);
+
+    // The second mutation fails on the duplicate key, so the first is rolled back
+    let response = request("mutation { a: createUser(id: 3) b: createUser(id: 1) }").await;
+    assert!(response["errors"].is_array());
+
+    // Resolvers can also discard successful changes
+    let response = request("mutation { a: createUser(id: 4) discard }").await;
+    assert_eq!(response["data"]["discard"], true);
+
+    let ids: Vec<(i32,)> = sqlx::query_as("SELECT id FROM users ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(ids, vec![(1,), (2,)]);
+    assert_eq!(state.open_transactions(), 0);
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "graphql-classifier")]
+
+use axum_sqlx_tx::GraphQlClassifier;
+use tower::ServiceExt;
+
+type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+
+#[tokio::test]
+async fn graphql_classifier() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY, name TEXT);")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (state, layer) = Tx::config(pool.clone())
+        .body_classifier(1024, GraphQlClassifier)
+        .setup();
+
+    let respond = |id: i32, response: serde_json::Value| {
+        move |mut tx: Tx| async move {
+            sqlx::query("INSERT INTO users VALUES (?, 'graphql')")
+                .bind(id)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            axum::Json(response)
+        }
+    };
+
+    let app = axum::Router::new()
+        .route(
+            "/data",
+            axum::routing::post(respond(1, serde_json::json!({ "data": { "ok": true } }))),
+        )
+        .route(
+            "/empty-errors",
+            axum::routing::post(respond(2, serde_json::json!({ "data": {}, "errors": [] }))),
+        )
+        .route(
+            "/errors",
+            axum::routing::post(respond(
+                3,
+                serde_json::json!({ "data": null, "errors": [{ "message": "oh no" }] }),
+            )),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    for uri in ["/data", "/empty-errors", "/errors"] {
+        let response = app
+            .clone()
+            .oneshot(
+                http::Request::builder()
+                    .method(http::Method::POST)
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+    }
+
+    let ids: Vec<(i32,)> = sqlx::query_as("SELECT id FROM users ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(ids, vec![(1,), (2,)]);
+}
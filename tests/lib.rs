@@ -270,56 +270,1870 @@ async fn overlapping_extractors() {
     let (_, response) = build_app(|_: Tx, _: Tx| async move {}).await;
 
     assert!(response.status.is_server_error());
+    let message = axum_sqlx_tx::Error::OverlappingExtractors { holder: None }.to_string();
+    assert!(response.body.starts_with(message.as_bytes()));
+
+    // In debug builds, the error says where the transaction was locked
+    let body = String::from_utf8(response.body.to_vec()).unwrap();
+    assert_eq!(body.contains("(held by src/tx.rs:"), cfg!(debug_assertions));
+}
+
+#[tokio::test]
+async fn overlapping_extractors_middleware() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static LINE: AtomicU32 = AtomicU32::new(0);
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    async fn test_middleware(
+        req: http::Request<axum::body::Body>,
+        next: middleware::Next,
+    ) -> impl IntoResponse {
+        LINE.store(line!() + 1, Ordering::Relaxed);
+        let tx = Tx::from_extensions(req.extensions()).await.unwrap();
+        let response = next.run(req).await;
+        drop(tx);
+        response
+    }
+
+    let (state, layer) = Tx::setup(pool);
+
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(|_tx: Tx| async move {}))
+        .layer(middleware::from_fn(test_middleware))
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_server_error());
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let message = axum_sqlx_tx::Error::OverlappingExtractors { holder: None }.to_string();
+    assert!(body.starts_with(&message));
+    if cfg!(debug_assertions) {
+        // The location of the `from_extensions` call in the middleware
+        let line = LINE.load(Ordering::Relaxed);
+        assert!(
+            body.contains(&format!("(held by {}:{line}:", file!())),
+            "{body}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn extractor_error_override() {
+    let (_, response) =
+        build_app(|_: Tx, _: axum_sqlx_tx::Tx<sqlx::Sqlite, MyExtractorError>| async move {}).await;
+
+    assert!(response.status.is_client_error());
+    assert_eq!(response.body, "internal server error");
+}
+
+#[tokio::test]
+async fn layer_error_override() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY);")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS comments (
+            id INT PRIMARY KEY,
+            user_id INT,
+            FOREIGN KEY (user_id) REFERENCES users(id) DEFERRABLE INITIALLY DEFERRED
+        );"#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let (state, layer) = Tx::config(pool).layer_error::<MyLayerError>().setup();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                sqlx::query("INSERT INTO comments VALUES (random(), random())")
+                    .execute(&mut tx)
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    assert!(status.is_client_error());
+    assert_eq!(body, "internal server error");
+}
+
+#[tokio::test]
+async fn pool_timeout() {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(std::time::Duration::from_millis(10))
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    let (state, layer) = Tx::config(pool.clone())
+        .retry_after(std::time::Duration::from_millis(1500))
+        .setup();
+
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(|_: Tx| async move {}))
+        .layer(layer)
+        .with_state(state);
+
+    // Hold the only connection so the extractor can't acquire one
+    let _conn = pool.acquire().await.unwrap();
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[http::header::RETRY_AFTER], "2");
+}
+
+#[tokio::test]
+async fn commit_error() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY);")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS comments (
+            id INT PRIMARY KEY,
+            user_id INT,
+            FOREIGN KEY (user_id) REFERENCES users(id) DEFERRABLE INITIALLY DEFERRED
+        );"#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let (state, layer) = Tx::setup(pool);
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                sqlx::query("INSERT INTO comments VALUES (random(), random())")
+                    .execute(&mut tx)
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body.starts_with(b"failed to commit transaction"));
+}
+
+#[tokio::test]
+async fn constraint_classifier_handler_error() {
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::config(pool.clone())
+        .constraint_classifier(axum_sqlx_tx::ConstraintClassifier::default())
+        .setup();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "dupe").await;
+                sqlx::query("INSERT INTO users VALUES (1, 'dupe')")
+                    .execute(&mut tx)
+                    .await?;
+                Ok::<_, axum_sqlx_tx::Error>(())
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    assert_eq!(status, http::StatusCode::CONFLICT);
+    assert_eq!(body, "Conflict");
+    assert_eq!(get_users(&pool).await, vec![]);
+}
+
+#[tokio::test]
+async fn constraint_classifier_commit_error() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY);")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS comments (
+            id INT PRIMARY KEY,
+            user_id INT,
+            FOREIGN KEY (user_id) REFERENCES users(id) DEFERRABLE INITIALLY DEFERRED
+        );"#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let classifier = axum_sqlx_tx::ConstraintClassifier::default().map(
+        axum_sqlx_tx::Constraint::ForeignKey,
+        http::StatusCode::UNPROCESSABLE_ENTITY,
+        "unknown user",
+    );
+    let (state, layer) = Tx::config(pool).constraint_classifier(classifier).setup();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                sqlx::query("INSERT INTO comments VALUES (random(), random())")
+                    .execute(&mut tx)
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    assert_eq!(status, http::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body, "unknown user");
+}
+
+#[tokio::test]
+async fn max_transactions() {
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (state, layer) = Tx::config(pool)
+        .max_transactions(1)
+        .on_saturation(axum_sqlx_tx::Saturation::Reject)
+        .setup();
+
+    let started = Arc::new(Notify::new());
+    let release = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/hold",
+            axum::routing::get({
+                let started = started.clone();
+                let release = release.clone();
+                |_: Tx| async move {
+                    started.notify_one();
+                    release.notified().await;
+                }
+            }),
+        )
+        .route("/tx", axum::routing::get(|_: Tx| async move {}))
+        .route("/no-tx", axum::routing::get(|| async move {}))
+        .layer(layer)
+        .with_state(state);
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let hold = tokio::spawn(request("/hold"));
+    started.notified().await;
+
+    let response = request("/tx").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = request("/no-tx").await.unwrap();
+    assert!(response.status().is_success());
+
+    release.notify_one();
+    assert!(hold.await.unwrap().unwrap().status().is_success());
+
+    let response = request("/tx").await.unwrap();
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn priority_order() {
+    use axum::Extension;
+    use axum_sqlx_tx::Priority;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::Notify;
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (state, layer) = Tx::config(pool).max_transactions(1).setup();
+
+    let started = Arc::new(Notify::new());
+    let release = Arc::new(Notify::new());
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    let record = |name: &'static str| {
+        let order = order.clone();
+        move |_: Tx| async move { order.lock().unwrap().push(name) }
+    };
+
+    let app = axum::Router::new()
+        .route(
+            "/hold",
+            axum::routing::get({
+                let started = started.clone();
+                let release = release.clone();
+                |_: Tx| async move {
+                    started.notify_one();
+                    release.notified().await;
+                }
+            }),
+        )
+        .route(
+            "/low",
+            axum::routing::get(record("low")).route_layer(Extension(Priority::Low)),
+        )
+        .route(
+            "/high",
+            axum::routing::get(record("high")).route_layer(Extension(Priority::High)),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let hold = tokio::spawn(request("/hold"));
+    started.notified().await;
+
+    let low = tokio::spawn(request("/low"));
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let high = tokio::spawn(request("/high"));
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    release.notify_one();
+    for response in [hold, low, high] {
+        assert!(response.await.unwrap().unwrap().status().is_success());
+    }
+
+    assert_eq!(*order.lock().unwrap(), vec!["high", "low"]);
+}
+
+#[tokio::test]
+async fn priority_reservation() {
+    use axum::Extension;
+    use axum_sqlx_tx::Priority;
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (state, layer) = Tx::config(pool)
+        .max_transactions(2)
+        .reserve(Priority::High, 1)
+        .on_saturation(axum_sqlx_tx::Saturation::Reject)
+        .setup();
+
+    let started = Arc::new(Notify::new());
+    let release = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/hold",
+            axum::routing::get({
+                let started = started.clone();
+                let release = release.clone();
+                |_: Tx| async move {
+                    started.notify_one();
+                    release.notified().await;
+                }
+            }),
+        )
+        .route("/normal", axum::routing::get(|_: Tx| async move {}))
+        .route(
+            "/high",
+            axum::routing::get(|_: Tx| async move {}).route_layer(Extension(Priority::High)),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let hold = tokio::spawn(request("/hold"));
+    started.notified().await;
+
+    let response = request("/normal").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = request("/high").await.unwrap();
+    assert!(response.status().is_success());
+
+    release.notify_one();
+    assert!(hold.await.unwrap().unwrap().status().is_success());
+}
+
+#[tokio::test]
+async fn drain() {
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::Notify;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let started = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/hold",
+            axum::routing::get({
+                let started = started.clone();
+                |mut tx: Tx| async move {
+                    insert_user(&mut tx, 1, "still running").await;
+                    drop(tx);
+                    started.notify_one();
+                    std::future::pending::<()>().await;
+                }
+            }),
+        )
+        .route("/", axum::routing::get(|_: Tx| async move {}))
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let hold = tokio::spawn(request("/hold"));
+    started.notified().await;
+    assert_eq!(state.open_transactions(), 1);
+
+    let drained = state.drain(Duration::from_millis(50)).await;
+    assert_eq!(drained.rolled_back, 1);
+    assert_eq!(drained.in_use, 0);
+    assert!(drained.errors.is_empty());
+    assert_eq!(state.open_transactions(), 0);
+    assert_eq!(get_users(&pool).await, vec![]);
+
+    let response = request("/").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    hold.abort();
+}
+
+#[tokio::test]
+async fn cancellation_safe() {
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::Notify;
+
+    let pool = users_pool().await;
+
+    let (observer, mut events_rx) = record_events();
+    let (state, layer) = Tx::config(pool.clone())
+        .cancellation_safe(true)
+        .observer(observer)
+        .setup();
+
+    let started = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/hang",
+            axum::routing::get({
+                let started = started.clone();
+                |mut tx: Tx| async move {
+                    insert_user(&mut tx, 1, "abandoned").await;
+                    started.notify_one();
+                    std::future::pending::<()>().await;
+                }
+            }),
+        )
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 2, "committed").await;
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    // Simulate a client disconnecting while the handler is running
+    let hang = tokio::spawn(request("/hang"));
+    started.notified().await;
+    hang.abort();
+
+    let event = events_rx.recv().await.unwrap();
+    assert_eq!(event, "Cancelled { rollback: RolledBack }");
+    assert_eq!(state.open_transactions(), 0);
+
+    let response = tokio::time::timeout(Duration::from_secs(1), request("/"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(response.status().is_success());
+
+    assert_eq!(get_users(&pool).await, vec![(2, "committed".to_string())]);
+}
+
+#[tokio::test]
+async fn panic_rollback() {
+    let pool = users_pool().await;
+
+    let app = |on_panic| {
+        let (observer, events_rx) = record_events();
+        let (state, layer) = Tx::config(pool.clone())
+            .on_panic(on_panic)
+            .observer(observer)
+            .setup();
+
+        let app = axum::Router::new()
+            .route("/", axum::routing::get(panicky))
+            .layer(layer)
+            .with_state(state);
+
+        let response = app.oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        );
+        (response, events_rx)
+    };
+
+    // By default, the panic is resumed
+    let (response, mut events) = app(axum_sqlx_tx::OnPanic::Resume);
+    let error = tokio::spawn(response).await.unwrap_err();
+    assert!(error.is_panic());
+    assert_eq!(
+        events.recv().await.unwrap(),
+        r#"Panicked { message: Some("oh no"), rollback: RolledBack }"#
+    );
+    assert_eq!(get_users(&pool).await, vec![]);
+
+    // The panic can be turned into a response instead
+    let (response, mut events) = app(axum_sqlx_tx::OnPanic::Respond);
+    let response = response.await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, axum_sqlx_tx::Error::Panicked.to_string());
+    assert!(events.recv().await.unwrap().starts_with("Panicked"));
+    assert_eq!(get_users(&pool).await, vec![]);
+}
+
+#[tokio::test]
+async fn panic_rollback_in_use() {
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    let pool = users_pool().await;
+
+    let (observer, mut events_rx) = record_events();
+    let (state, layer) = Tx::config(pool.clone())
+        .on_panic(axum_sqlx_tx::OnPanic::Respond)
+        .observer(observer)
+        .setup();
+
+    let release = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(
+                |axum::Extension(release): axum::Extension<Arc<Notify>>, mut tx: Tx| async move {
+                    insert_user(&mut tx, 1, "spawned").await;
+                    // The transaction is still in use when the handler panics
+                    tokio::spawn(async move {
+                        release.notified().await;
+                        drop(tx);
+                    });
+                    panic!("oh no") as ()
+                },
+            ),
+        )
+        .layer(axum::Extension(release.clone()))
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        events_rx.recv().await.unwrap(),
+        r#"Panicked { message: Some("oh no"), rollback: InUse }"#
+    );
+
+    release.notify_one();
+}
+
+#[tokio::test]
+async fn rollback_error() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (observer, mut events_rx) = record_events();
+    let (state, layer) = Tx::config(pool.clone()).observer(observer).setup();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                // End the transaction behind sqlx's back, so that rolling back fails
+                sqlx::query("COMMIT").execute(&mut tx).await.unwrap();
+                http::StatusCode::BAD_REQUEST
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+    // The rollback has been awaited by the time the response is returned
+    let event = events_rx.try_recv().unwrap();
+    assert!(event.starts_with("RollbackFailed"), "{event}");
+
+    // The connection is closed rather than returned to the pool
+    tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        while pool.size() > 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(pool.num_idle(), 0);
+}
+
+#[tokio::test]
+async fn continue_transaction() {
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/commit",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "committed").await;
+                tx.commit_and_continue().await.unwrap();
+                insert_user(&mut tx, 2, "rolled back").await;
+                http::StatusCode::BAD_REQUEST
+            }),
+        )
+        .route(
+            "/rollback",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 3, "rolled back").await;
+                tx.rollback_and_continue().await.unwrap();
+                insert_user(&mut tx, 4, "committed").await;
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    for (uri, status) in [
+        ("/commit", http::StatusCode::BAD_REQUEST),
+        ("/rollback", http::StatusCode::OK),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                http::Request::builder()
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status);
+    }
+
+    assert_eq!(
+        get_users(&pool).await,
+        vec![(1, "committed".to_string()), (4, "committed".to_string())]
+    );
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn independent_tx() {
+    use std::time::Duration;
+
+    use axum_sqlx_tx::IndependentTx;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/explicit",
+            axum::routing::get(
+                |mut audit: IndependentTx<sqlx::Sqlite>, mut tx: Tx| async move {
+                    sqlx::query("INSERT INTO users VALUES (1, 'audited')")
+                        .execute(&mut audit)
+                        .await
+                        .unwrap();
+                    audit.commit().await.unwrap();
+
+                    insert_user(&mut tx, 2, "rolled back").await;
+                    http::StatusCode::INTERNAL_SERVER_ERROR
+                },
+            ),
+        )
+        .route(
+            "/drop",
+            axum::routing::get(|mut audit: IndependentTx<sqlx::Sqlite>| async move {
+                sqlx::query("INSERT INTO users VALUES (3, 'audited')")
+                    .execute(&mut audit)
+                    .await
+                    .unwrap();
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    for uri in ["/explicit", "/drop"] {
+        let response = app
+            .clone()
+            .oneshot(
+                http::Request::builder()
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    // Committing on drop happens in the background
+    let users = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            let users = get_users(&pool).await;
+            if users.len() == 2 {
+                break users;
+            }
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        users,
+        vec![(1, "audited".to_string()), (3, "audited".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn conn() {
+    use axum_sqlx_tx::Conn;
+
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    create_users(&pool).await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut conn: Conn<sqlx::Sqlite>| async move {
+                sqlx::query("INSERT INTO users VALUES (1, 'autocommitted')")
+                    .execute(&mut conn)
+                    .await
+                    .unwrap();
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    // The statement wasn't part of a transaction, and the only connection is back in the pool
+    assert_eq!(
+        get_users(&pool).await,
+        vec![(1, "autocommitted".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn conn_released_before_body() {
+    use axum_sqlx_tx::Conn;
+    use http_body_util::BodyExt as _;
+
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(2)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    let (state, layer) = Tx::config(pool.clone()).resolve_on_body_end(true).setup();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|_conn: Conn<sqlx::Sqlite>, _tx: Tx| async move {}),
+        )
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // The connection is back in the pool while the transaction waits for the body to end
+    tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        while pool.num_idle() == 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(pool.size(), 2);
+
+    let body = response.into_body().collect().await.unwrap();
+    assert_eq!(
+        body.trailers().unwrap()["x-transaction-outcome"],
+        "committed"
+    );
+}
+
+#[tokio::test]
+async fn lazy_tx() {
+    type LazyTx = axum_sqlx_tx::LazyTx<sqlx::Sqlite>;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get({
+                let state = state.clone();
+                |mut tx: LazyTx| async move {
+                    assert_eq!(state.open_transactions(), 0);
+
+                    // `fetch_all` uses `fetch_many`, which begins the transaction
+                    let users: Vec<(i32, String)> = sqlx::query_as("SELECT * FROM users")
+                        .fetch_all(&mut tx)
+                        .await
+                        .unwrap();
+                    assert_eq!(users, vec![]);
+                    assert_eq!(state.open_transactions(), 1);
+
+                    insert_user(tx.ensure_begun().await.unwrap(), 1, "lazy").await;
+                }
+            }),
+        )
+        .route(
+            "/early",
+            axum::routing::get(|_: LazyTx| async move { http::StatusCode::BAD_REQUEST }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    for (uri, status) in [
+        ("/early", http::StatusCode::BAD_REQUEST),
+        ("/", http::StatusCode::OK),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                http::Request::builder()
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status);
+    }
+
+    assert_eq!(get_users(&pool).await, vec![(1, "lazy".to_string())]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn lazy_tx_error() {
+    type LazyTx = axum_sqlx_tx::LazyTx<sqlx::Sqlite>;
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get({
+                let state = state.clone();
+                |mut tx: LazyTx| async move {
+                    state.drain(std::time::Duration::ZERO).await;
+
+                    let error = sqlx::query("SELECT 1").execute(&mut tx).await.unwrap_err();
+                    assert!(matches!(error, sqlx::Error::Configuration(_)));
+
+                    let error = axum_sqlx_tx::Error::from(error);
+                    assert!(matches!(error, axum_sqlx_tx::Error::Draining));
+                    Err::<(), _>(error)
+                }
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn generic_service() {
+    use axum_sqlx_tx::generic::{Layer, RequestExtensions};
+    use tower::{Service as _, ServiceBuilder};
+
+    struct Job {
+        extensions: http::Extensions,
+        id: i32,
+        fail: bool,
+    }
+
+    impl RequestExtensions for Job {
+        fn extensions(&self) -> &http::Extensions {
+            &self.extensions
+        }
+
+        fn extensions_mut(&mut self) -> &mut http::Extensions {
+            &mut self.extensions
+        }
+    }
+
+    let pool = users_pool().await;
+
+    let (state, _) = Tx::setup(pool.clone());
+
+    let mut consumer = ServiceBuilder::new()
+        .layer(Layer::new(
+            state.clone(),
+            |result: &Result<(), axum_sqlx_tx::Error>| result.is_ok(),
+        ))
+        .service_fn(|job: Job| async move {
+            let mut tx = Tx::from_extensions(job.extensions()).await?;
+            insert_user(&mut tx, job.id, "job").await;
+            if job.fail {
+                return Err(axum_sqlx_tx::Error::MissingExtension);
+            }
+            Ok(())
+        });
+
+    for (id, fail) in [(1, false), (2, true)] {
+        let job = Job {
+            extensions: http::Extensions::new(),
+            id,
+            fail,
+        };
+        let result = consumer.call(job).await;
+        assert_eq!(result.is_ok(), !fail);
+    }
+
+    assert_eq!(get_users(&pool).await, vec![(1, "job".to_string())]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn grpc() {
+    use http_body_util::BodyExt as _;
+    use tonic::{codec::ProstCodec, server::Grpc, Status};
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::config(pool.clone()).grpc(true).setup();
+
+    // A unary gRPC method that inserts the user with the id given in the metadata
+    let method = tower::service_fn(|request: tonic::Request<()>| async move {
+        let id: i32 = request
+            .metadata()
+            .get("id")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut tx = Tx::from_extensions(request.extensions()).await.unwrap();
+        insert_user(&mut tx, id, "grpc").await;
+        if id == 2 {
+            return Err(Status::invalid_argument("nope"));
+        }
+        Ok(tonic::Response::new(()))
+    });
+    let server = tower::service_fn(move |request: http::Request<axum::body::Body>| async move {
+        let mut grpc = Grpc::new(ProstCodec::<(), ()>::default());
+        Ok::<_, std::convert::Infallible>(grpc.unary(method, request).await)
+    });
+    let server = tower::ServiceBuilder::new().layer(layer).service(server);
+
+    let call = |id: i32| {
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/test.Users/Insert")
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .header("id", id)
+            // An uncompressed, empty message
+            .body(axum::body::Body::from(vec![0u8; 5]))
+            .unwrap();
+        server.clone().oneshot(request)
+    };
+
+    // OK is reported in the trailers, and the transaction is committed once the body ends
+    let response = call(1).await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert!(response.headers().get("grpc-status").is_none());
+    let trailers = response
+        .into_body()
+        .collect()
+        .await
+        .unwrap()
+        .trailers()
+        .cloned();
+    assert_eq!(trailers.unwrap()["grpc-status"], "0");
+
+    // Errors are reported in the headers, and the transaction is rolled back
+    let response = call(2).await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.headers()["grpc-status"], "3");
+
+    assert_eq!(get_users(&pool).await, vec![(1, "grpc".to_string())]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn resolve_on_body_end() {
+    use http_body_util::BodyExt as _;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::config(pool.clone()).resolve_on_body_end(true).setup();
+
+    // The body uses the transaction while it's streamed, and fails if asked to
+    let stream = |mut tx: Tx, id: i32, fail: bool| {
+        axum::body::Body::from_stream(futures_util::stream::once(async move {
+            let (id, name) = insert_user(&mut tx, id, "streamed").await;
+            if fail {
+                return Err(std::io::Error::other("oh no"));
+            }
+            Ok(format!("{id}: {name}"))
+        }))
+    };
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(move |tx: Tx| async move { stream(tx, 1, false) }),
+        )
+        .route(
+            "/fail",
+            axum::routing::get(move |tx: Tx| async move { stream(tx, 2, true) }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let response = request("/").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap();
+    assert_eq!(
+        body.trailers().unwrap()["x-transaction-outcome"],
+        "committed"
+    );
+    assert_eq!(body.to_bytes(), "1: streamed");
+
+    let response = request("/fail").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert!(response.into_body().collect().await.is_err());
+
+    assert_eq!(get_users(&pool).await, vec![(1, "streamed".to_string())]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn resolve_on_body_end_drained() {
+    use http_body_util::BodyExt as _;
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::Notify;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::config(pool.clone()).resolve_on_body_end(true).setup();
+
+    let release = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get({
+                let release = release.clone();
+                |mut tx: Tx| async move {
+                    insert_user(&mut tx, 1, "drained").await;
+                    drop(tx);
+                    axum::body::Body::from_stream(futures_util::stream::once(async move {
+                        release.notified().await;
+                        Ok::<_, std::io::Error>("done")
+                    }))
+                }
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    // Shut down while the body is streamed, rolling back the transaction
+    let drained = state.drain(Duration::ZERO).await;
+    assert_eq!(drained.rolled_back, 1);
+
+    release.notify_one();
+    let body = response.into_body().collect().await.unwrap();
+    assert_eq!(
+        body.trailers().unwrap()["x-transaction-outcome"],
+        "commit-failed"
+    );
+
+    assert_eq!(get_users(&pool).await, vec![]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn resolve_on_body_end_served() {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::config(pool.clone()).resolve_on_body_end(true).setup();
+
+    // A body with an exact size, which servers might stop polling before its end
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "served").await;
+                "hello"
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nhost: localhost\r\nte: trailers\r\nconnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    assert!(
+        response.contains("x-transaction-outcome: committed"),
+        "{response}"
+    );
+    assert_eq!(get_users(&pool).await, vec![(1, "served".to_string())]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn tx_stream() {
+    use axum_sqlx_tx::TxStream;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "one").await;
+                insert_user(&mut tx, 2, "two").await;
+                TxStream::new(
+                    tx,
+                    |tx| sqlx::query_as("SELECT * FROM users ORDER BY id").fetch(tx),
+                    |(id, name): (i32, String)| format!("{id},{name}\n").into(),
+                )
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    // The transaction stays open until the body has been streamed
+    assert_eq!(state.open_transactions(), 1);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, "1,one\n2,two\n");
+
+    assert_eq!(state.open_transactions(), 0);
+    assert_eq!(
+        get_users(&pool).await,
+        vec![(1, "one".to_string()), (2, "two".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn tx_stream_error() {
+    use axum_sqlx_tx::TxStream;
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "one").await;
+                // Names can't be decoded as integers, so the query fails on the first row
+                TxStream::new(
+                    tx,
+                    |tx| sqlx::query_as("SELECT * FROM users").fetch(tx),
+                    |(id, name): (i32, i32)| format!("{id},{name}\n").into(),
+                )
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_err();
+
+    assert_eq!(state.open_transactions(), 0);
+    assert_eq!(get_users(&pool).await, vec![]);
+}
+
+#[tokio::test]
+async fn body_classifier() {
+    let pool = users_pool().await;
+
+    let (observer, mut events_rx) = record_events();
+    let (state, layer) = Tx::config(pool.clone())
+        .body_classifier(16, |_: &http::response::Parts, body: &axum::body::Bytes| {
+            !body.starts_with(b"error")
+        })
+        .observer(observer)
+        .setup();
+
+    let app = axum::Router::new()
+        .route(
+            "/ok",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "ok").await;
+                "ok"
+            }),
+        )
+        .route(
+            "/error",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 2, "error").await;
+                "error: oh no"
+            }),
+        )
+        .route(
+            "/large",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 3, "large").await;
+                "a body that's over the limit"
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    http::Request::builder()
+                        .uri(uri)
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        }
+    };
+
+    // The buffered body is still sent in full
+    assert_eq!(request("/ok").await, "ok");
+    assert_eq!(request("/error").await, "error: oh no");
+    assert_eq!(request("/large").await, "a body that's over the limit");
+
+    assert_eq!(get_users(&pool).await, vec![(1, "ok".to_string())]);
     assert_eq!(
-        response.body,
-        format!("{}", axum_sqlx_tx::Error::OverlappingExtractors)
+        events_rx.try_recv().unwrap(),
+        "BodyLimitExceeded { limit: 16 }"
     );
+    assert_eq!(state.open_transactions(), 0);
 }
 
 #[tokio::test]
-async fn extractor_error_override() {
-    let (_, response) =
-        build_app(|_: Tx, _: axum_sqlx_tx::Tx<sqlx::Sqlite, MyExtractorError>| async move {}).await;
+async fn body_classifier_cancelled() {
+    use std::sync::Arc;
+    use tokio::sync::Notify;
 
-    assert!(response.status.is_client_error());
-    assert_eq!(response.body, "internal server error");
+    let pool = users_pool().await;
+
+    let (observer, mut events_rx) = record_events();
+    let (state, layer) = Tx::config(pool.clone())
+        .cancellation_safe(true)
+        .body_classifier(16, |_: &http::response::Parts, _: &axum::body::Bytes| true)
+        .observer(observer)
+        .setup();
+
+    let started = Arc::new(Notify::new());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get({
+                let started = started.clone();
+                |mut tx: Tx| async move {
+                    insert_user(&mut tx, 1, "abandoned").await;
+                    axum::body::Body::from_stream(futures_util::stream::once(async move {
+                        started.notify_one();
+                        std::future::pending::<Result<axum::body::Bytes, std::io::Error>>().await
+                    }))
+                }
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    // Simulate a client disconnecting while the body is being buffered
+    let hang = tokio::spawn(
+        app.oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        ),
+    );
+    started.notified().await;
+    hang.abort();
+
+    let event = events_rx.recv().await.unwrap();
+    assert_eq!(event, "Cancelled { rollback: RolledBack }");
+    assert_eq!(state.open_transactions(), 0);
+    assert_eq!(get_users(&pool).await, vec![]);
 }
 
 #[tokio::test]
-async fn layer_error_override() {
-    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+async fn from_tx() {
+    use axum_sqlx_tx::FromTx;
+
+    type Loaded<T> = axum_sqlx_tx::Loaded<sqlx::Sqlite, T>;
+
+    struct CurrentUser(i32, String);
+
+    impl FromTx<sqlx::Sqlite> for CurrentUser {
+        type Rejection = http::StatusCode;
+
+        async fn from_tx(
+            parts: &mut http::request::Parts,
+            tx: &mut Tx,
+        ) -> Result<Self, Self::Rejection> {
+            let id: i32 = parts
+                .headers
+                .get("x-user-id")
+                .and_then(|id| id.to_str().ok()?.parse().ok())
+                .ok_or(http::StatusCode::UNAUTHORIZED)?;
+            let (id, name) = sqlx::query_as("SELECT id, name FROM users WHERE id = ?")
+                .bind(id)
+                .fetch_optional(tx)
+                .await
+                .unwrap()
+                .ok_or(http::StatusCode::UNAUTHORIZED)?;
+            Ok(Self(id, name))
+        }
+    }
 
-    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY);")
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|user: Loaded<CurrentUser>, mut tx: Tx| async move {
+                // The transaction can still be used after loading the user
+                insert_user(&mut tx, user.0 + 1, &format!("{}'s friend", user.1)).await;
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |user: &str| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri("/")
+                .header("x-user-id", user)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let response = request("1").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+    sqlx::query("INSERT INTO users VALUES (1, 'alice')")
         .execute(&pool)
         .await
         .unwrap();
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS comments (
-            id INT PRIMARY KEY,
-            user_id INT,
-            FOREIGN KEY (user_id) REFERENCES users(id) DEFERRABLE INITIALLY DEFERRED
-        );"#,
-    )
-    .execute(&pool)
-    .await
-    .unwrap();
 
-    let (state, layer) = Tx::config(pool).layer_error::<MyLayerError>().setup();
+    let response = request("1").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
 
-    let app = axum::Router::new()
+    assert_eq!(
+        get_users(&pool).await,
+        vec![(1, "alice".to_string()), (2, "alice's friend".to_string())]
+    );
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn nested_layer() {
+    let pool = users_pool().await;
+
+    async fn test_middleware(
+        req: http::Request<axum::body::Body>,
+        next: middleware::Next,
+    ) -> impl IntoResponse {
+        let extensions = req.extensions().clone();
+        let res = next.run(req).await;
+        assert!(res.status().is_success());
+
+        // Use the outer layer's transaction after the nested layer has responded
+        let mut tx = Tx::from_extensions(&extensions).await.unwrap();
+        insert_user(&mut tx, 2, "outer").await;
+        http::StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let nested = axum::Router::new()
         .route(
             "/",
             axum::routing::get(|mut tx: Tx| async move {
-                sqlx::query("INSERT INTO comments VALUES (random(), random())")
-                    .execute(&mut tx)
-                    .await
-                    .unwrap();
+                insert_user(&mut tx, 1, "nested").await;
             }),
         )
+        .layer(layer.clone());
+
+    let app = axum::Router::new()
+        .nest("/nested", nested)
+        .layer(middleware::from_fn(test_middleware))
+        .layer(layer)
+        .with_state(state.clone());
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/nested")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_server_error());
+
+    // Both layers used the same transaction, which the outer layer rolled back
+    assert_eq!(get_users(&pool).await, vec![]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn conflicting_layers() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (state, layer) = Tx::setup(pool.clone());
+    let (_, other_layer) = Tx::setup(pool);
+
+    // The nested layer has a different `State`, so it can't share the outer layer's transaction
+    let nested = axum::Router::new()
+        .route("/", axum::routing::get(|_: Tx| async move {}))
+        .layer(other_layer);
+
+    let app = axum::Router::new()
+        .nest("/nested", nested)
         .layer(layer)
         .with_state(state);
 
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/nested")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_server_error());
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, axum_sqlx_tx::Error::ConflictingLayers.to_string());
+}
+
+#[tokio::test]
+async fn transactional() {
+    use axum_sqlx_tx::Transactional;
+    use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions as _, Connection as _};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    // Use a database file, so that another connection can hold a lock on it
+    let path = std::env::temp_dir().join(format!(
+        "axum-sqlx-tx-transactional-{}.db",
+        std::process::id()
+    ));
+    let options = SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true)
+        .busy_timeout(Duration::ZERO);
+    let pool = sqlx::SqlitePool::connect_with(options.clone())
+        .await
+        .unwrap();
+
+    create_users(&pool).await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let mut lock = options.connect().await.unwrap();
+    sqlx::query("BEGIN EXCLUSIVE")
+        .execute(&mut lock)
+        .await
+        .unwrap();
+    let lock = Arc::new(tokio::sync::Mutex::new(Some(lock)));
+    let attempts = Arc::new(AtomicUsize::new(0));
+
+    let app = axum::Router::new()
+        .route(
+            "/retry",
+            axum::routing::get({
+                let attempts = attempts.clone();
+                move |tx: Transactional<Tx>| async move {
+                    tx.retry(1)
+                        .run(|mut tx: Tx| {
+                            let (lock, attempts) = (lock.clone(), attempts.clone());
+                            async move {
+                                attempts.fetch_add(1, Ordering::SeqCst);
+                                let result = sqlx::query("INSERT INTO users VALUES (1, 'retry')")
+                                    .execute(&mut tx)
+                                    .await;
+
+                                // Release the lock, so that the retry succeeds
+                                if let Some(lock) = lock.lock().await.take() {
+                                    lock.close().await.unwrap();
+                                }
+
+                                result.map(drop).map_err(axum_sqlx_tx::Error::from)
+                            }
+                        })
+                        .await
+                }
+            }),
+        )
+        .route(
+            "/rollback",
+            axum::routing::get(|tx: Transactional<Tx>| async move {
+                tx.run(|mut tx: Tx| async move {
+                    insert_user(&mut tx, 2, "rollback").await;
+                    http::StatusCode::BAD_REQUEST
+                })
+                .await
+            }),
+        )
+        .route(
+            "/timeout",
+            axum::routing::get(|tx: Transactional<Tx>| async move {
+                tx.timeout(Duration::from_millis(10))
+                    .run(|mut tx: Tx| async move {
+                        insert_user(&mut tx, 3, "timeout").await;
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    })
+                    .await
+            }),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    // The first attempt fails with `SQLITE_BUSY`, and is retried
+    let response = request("/retry").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+    let response = request("/rollback").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+    let response = request("/timeout").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    assert_eq!(get_users(&pool).await, vec![(1, "retry".to_string())]);
+    assert_eq!(state.open_transactions(), 0);
+
+    pool.close().await;
+    std::fs::remove_file(&path).ok();
+}
+
+#[tokio::test]
+async fn transactional_already_begun() {
+    use axum_sqlx_tx::Transactional;
+
+    async fn test_middleware(
+        mut tx: Tx,
+        req: http::Request<axum::body::Body>,
+        next: middleware::Next,
+    ) -> impl IntoResponse {
+        insert_user(&mut tx, 1, "middleware").await;
+        drop(tx);
+        next.run(req).await
+    }
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Transactional<Tx>| async move {
+                tx.run(|_: Tx| async move { http::StatusCode::OK }).await
+            }),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            test_middleware,
+        ))
+        .layer(layer)
+        .with_state(state.clone());
+
     let response = app
         .oneshot(
             http::Request::builder()
@@ -329,13 +2143,56 @@ async fn layer_error_override() {
         )
         .await
         .unwrap();
-    let status = response.status();
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
     let body = axum::body::to_bytes(response.into_body(), usize::MAX)
         .await
         .unwrap();
+    assert!(body.starts_with(b"transaction already begun"), "{body:?}");
 
-    assert!(status.is_client_error());
-    assert_eq!(body, "internal server error");
+    assert_eq!(get_users(&pool).await, vec![]);
+    assert_eq!(state.open_transactions(), 0);
+}
+
+#[tokio::test]
+async fn tx_options() {
+    use axum_sqlx_tx::{IsolationLevel, TxOptions};
+
+    let pool = users_pool().await;
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    // SQLite doesn't support these options, so they're not applied
+    let options = TxOptions::new()
+        .isolation(IsolationLevel::Serializable)
+        .read_only(true);
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                insert_user(&mut tx, 1, "huge hackerman").await;
+            })
+            .route_layer(axum::Extension(options)),
+        )
+        .layer(layer)
+        .with_state(state.clone());
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    assert_eq!(
+        get_users(&pool).await,
+        vec![(1, "huge hackerman".to_string())]
+    );
+    assert_eq!(state.open_transactions(), 0);
 }
 
 #[tokio::test]
@@ -425,6 +2282,11 @@ async fn multi_db() {
     assert!(status.is_success());
 }
 
+async fn panicky(mut tx: Tx) {
+    insert_user(&mut tx, 1, "panicky").await;
+    panic!("oh no");
+}
+
 async fn insert_user(tx: &mut Tx, id: i32, name: &str) -> (i32, String) {
     let mut args = SqliteArguments::default();
     args.add(id).unwrap();
@@ -445,6 +2307,32 @@ async fn get_users(pool: &sqlx::SqlitePool) -> Vec<(i32, String)> {
         .unwrap()
 }
 
+/// An in-memory database with an empty `users` table.
+async fn users_pool() -> sqlx::SqlitePool {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+    create_users(&pool).await;
+    pool
+}
+
+async fn create_users(pool: &sqlx::SqlitePool) {
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY, name TEXT);")
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// An observer that sends the `Debug` output of each event to the returned receiver.
+fn record_events() -> (
+    impl axum_sqlx_tx::Observer,
+    tokio::sync::mpsc::UnboundedReceiver<String>,
+) {
+    let (events, events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let observer = move |event: axum_sqlx_tx::Event<'_>| {
+        events.send(format!("{event:?}")).unwrap();
+    };
+    (observer, events_rx)
+}
+
 struct Response {
     status: http::StatusCode,
     body: axum::body::Bytes,
@@ -503,11 +2391,11 @@ impl IntoResponse for MyExtractorError {
 }
 
 struct MyLayerError {
-    _0: sqlx::Error,
+    _0: axum_sqlx_tx::Error,
 }
 
-impl From<sqlx::Error> for MyLayerError {
-    fn from(error: sqlx::Error) -> Self {
+impl From<axum_sqlx_tx::Error> for MyLayerError {
+    fn from(error: axum_sqlx_tx::Error) -> Self {
         Self { _0: error }
     }
 }
@@ -0,0 +1,94 @@
+#![cfg(feature = "macros")]
+
+use axum::extract::Path;
+use axum_sqlx_tx::{transactional, State};
+use tower::ServiceExt;
+
+type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+
+#[transactional(timeout = "1s", state = State<sqlx::Sqlite>)]
+async fn insert(mut tx: Tx, Path((id, name)): Path<(i32, String)>) -> http::StatusCode {
+    sqlx::query("INSERT INTO users VALUES (?, ?)")
+        .bind(id)
+        .bind(&name)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+    if name == "rollback" {
+        http::StatusCode::BAD_REQUEST
+    } else {
+        http::StatusCode::OK
+    }
+}
+
+// With retries, the other arguments are cloned for each attempt
+#[transactional(retry = 1, timeout = "10ms")]
+async fn slow(headers: http::HeaderMap, mut tx: Tx) {
+    assert!(headers.is_empty());
+    sqlx::query("INSERT INTO users VALUES (3, 'slow')")
+        .execute(&mut tx)
+        .await
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+}
+
+// Aliases that aren't named `Tx` are given with the `tx` option
+type UsersTx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+
+#[transactional(tx = UsersTx)]
+async fn renamed(mut tx: UsersTx) {
+    sqlx::query("INSERT INTO users VALUES (4, 'renamed')")
+        .execute(&mut tx)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn transactional() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY, name TEXT);")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (state, layer) = Tx::setup(pool.clone());
+
+    let app = axum::Router::new()
+        .route("/insert/{id}/{name}", axum::routing::get(insert))
+        .route("/slow", axum::routing::get(slow))
+        .route("/renamed", axum::routing::get(renamed))
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let response = request("/insert/1/alice").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let response = request("/insert/2/rollback").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+    let response = request("/slow").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = request("/renamed").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let users: Vec<(i32, String)> = sqlx::query_as("SELECT * FROM users")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        users,
+        vec![(1, "alice".to_string()), (4, "renamed".to_string())]
+    );
+    assert_eq!(state.open_transactions(), 0);
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "problem-json")]
+
+use axum_sqlx_tx::problem::Problem;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn problem_response() {
+    let (status, content_type, body) = overlapping::<Problem>().await;
+
+    assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(content_type, "application/problem+json");
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "type": "urn:axum-sqlx-tx:error:overlapping-extractors",
+            "title": "Misconfigured transaction",
+            "status": 500,
+        })
+    );
+}
+
+#[tokio::test]
+async fn verbose_problem_response() {
+    let (_, _, body) = overlapping::<Problem<true>>().await;
+
+    let message = axum_sqlx_tx::Error::OverlappingExtractors { holder: None }.to_string();
+    let detail = body["detail"].as_str().unwrap();
+    assert!(detail.starts_with(&message));
+    assert_eq!(detail.contains("(held by "), cfg!(debug_assertions));
+}
+
+async fn overlapping<E>() -> (http::StatusCode, String, serde_json::Value)
+where
+    E: From<axum_sqlx_tx::Error> + axum::response::IntoResponse + Send + Sync + 'static,
+{
+    type Tx<E> = axum_sqlx_tx::Tx<sqlx::Sqlite, E>;
+
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    let (state, layer) = Tx::<E>::config(pool).layer_error::<E>().setup();
+
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(|_: Tx<E>, _: Tx<E>| async move {}))
+        .layer(layer)
+        .with_state(state);
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let content_type = response.headers()[http::header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (status, content_type, serde_json::from_slice(&body).unwrap())
+}
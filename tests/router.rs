@@ -0,0 +1,45 @@
+#![cfg(feature = "axum")]
+
+use axum_sqlx_tx::RouterExt;
+use tower::ServiceExt;
+
+type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+
+#[tokio::test]
+async fn with_tx() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY, name TEXT);")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                sqlx::query("INSERT INTO users VALUES (1, 'huge hackerman')")
+                    .execute(&mut tx)
+                    .await
+                    .unwrap();
+            }),
+        )
+        .with_tx(Tx::config(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let users: Vec<(i32, String)> = sqlx::query_as("SELECT * FROM users")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(users, vec![(1, "huge hackerman".to_string())]);
+}
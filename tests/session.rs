@@ -0,0 +1,89 @@
+#![cfg(feature = "tower-sessions")]
+
+use axum_sqlx_tx::session::Store;
+use tower::ServiceExt;
+use tower_sessions::{ExpiredDeletion, Session, SessionManagerLayer};
+
+type Tx = axum_sqlx_tx::Tx<sqlx::Sqlite>;
+
+#[tokio::test]
+async fn session_store() {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS users (id INT PRIMARY KEY, name TEXT);")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (state, layer) = Tx::setup(pool.clone());
+    let store = Store::new(state.clone());
+    store.migrate().await.unwrap();
+
+    let login = |status: http::StatusCode| {
+        move |mut tx: Tx, session: Session| async move {
+            sqlx::query("INSERT INTO users VALUES (1, 'session')")
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            session.insert("user", 1).await.unwrap();
+            status
+        }
+    };
+
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(login(http::StatusCode::OK)))
+        .route(
+            "/fail",
+            axum::routing::get(login(http::StatusCode::BAD_REQUEST)),
+        )
+        .route(
+            "/no-tx",
+            axum::routing::get(|session: Session| async move {
+                session.insert("user", 2).await.unwrap();
+            }),
+        )
+        .layer(SessionManagerLayer::new(store.clone()))
+        .layer(layer)
+        .with_state(state.clone());
+
+    let request = |uri| {
+        app.clone().oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+    };
+
+    // The session is written through the request's transaction, so it's rolled back with it
+    let response = request("/fail").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    assert_eq!(count(&pool, "users").await, 0);
+    assert_eq!(count(&pool, "tower_sessions").await, 0);
+
+    let response = request("/").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(count(&pool, "users").await, 1);
+    assert_eq!(count(&pool, "tower_sessions").await, 1);
+
+    // Without a transaction, the session is written using the pool
+    let response = request("/no-tx").await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(count(&pool, "tower_sessions").await, 2);
+    assert_eq!(state.open_transactions(), 0);
+
+    // Expired sessions are deleted
+    sqlx::query("UPDATE tower_sessions SET expiry_date = 0")
+        .execute(&pool)
+        .await
+        .unwrap();
+    store.delete_expired().await.unwrap();
+    assert_eq!(count(&pool, "tower_sessions").await, 0);
+}
+
+async fn count(pool: &sqlx::SqlitePool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}